@@ -0,0 +1,55 @@
+use sys_media::MediaInfo;
+
+/// Titles players like Spotify give to the ads they play. Used when AMPLE_AD_TITLES isn't set.
+pub const DEFAULT_AD_TITLES: [&str; 3] = ["Advertisement", "Spotify", "Ad"];
+
+/// Guesses whether the media is an ad rather than an actual track.
+///
+/// Ads are detected by either having no artist, which real tracks always have,
+/// or by having one of `ad_titles` as the title (ignoring case).
+/// Non music media like videos isn't treated as an ad, it's kept out of scrobbles by AMPLE_SCROBBLE_MEDIA_TYPES instead.
+pub fn is_advertisement(info: &MediaInfo, ad_titles: &[String]) -> bool {
+    info.artist_name.trim().is_empty() || has_ad_title(info, ad_titles)
+}
+
+/// Whether the title is one of `ad_titles` (ignoring case). Unlike a missing artist, which players also report
+/// for a moment while switching tracks, this doesn't happen to real tracks.
+pub fn has_ad_title(info: &MediaInfo, ad_titles: &[String]) -> bool {
+    let title = info.song_name.trim();
+
+    ad_titles.iter().any(|ad_title| ad_title.eq_ignore_ascii_case(title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    fn media(song_name: &str, artist_name: &str) -> MediaInfo {
+        MediaInfo {
+            song_name: song_name.to_owned(),
+            artist_name: artist_name.to_owned(),
+            album_name: String::new(),
+            player_name: "Spotify.exe".to_owned(),
+            end_time: 30_000_000,
+            ..testing::media()
+        }
+    }
+
+    #[test]
+    fn advertisements() {
+        let ad_titles: Vec<String> = DEFAULT_AD_TITLES.iter().map(|title| title.to_string()).collect();
+
+        assert!(is_advertisement(&media("Advertisement", "Spotify"), &ad_titles));
+        assert!(is_advertisement(&media("advertisement", "Someone"), &ad_titles));
+        assert!(is_advertisement(&media("Great Deals", ""), &ad_titles));
+        assert!(is_advertisement(&media("Great Deals", "  "), &ad_titles));
+
+        assert!(!is_advertisement(&media("7", "Catfish and the Bottlemen"), &ad_titles));
+        assert!(!is_advertisement(&media("Advertising Space", "Robbie Williams"), &ad_titles));
+        assert!(!is_advertisement(&media("Advertisement", "Spotify"), &[]));
+
+        assert!(has_ad_title(&media("Advertisement", ""), &ad_titles));
+        assert!(!has_ad_title(&media("Great Deals", ""), &ad_titles));
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::Hash,
+};
+
+/// A small least-recently-used cache. Once `capacity` entries are stored,
+/// inserting a new entry evicts the one that was used the longest time ago.
+#[derive(Debug)]
+pub struct LruCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    // front is the least recently used key
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> LruCache<K, V> {
+    pub fn new(capacity: usize) -> LruCache<K, V> {
+        LruCache {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Gets a copy of the value for `key`, marking it as the most recently used.
+    pub fn get(&mut self, key: &K) -> Option<V> {
+        let value = self.entries.get(key)?.clone();
+        self.touch(key);
+
+        Some(value)
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.insert(key.clone(), value).is_some() {
+            self.touch(&key);
+            return;
+        }
+
+        self.order.push_back(key);
+        if self.order.len() > self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.entries.remove(&oldest);
+        }
+    }
+
+    fn touch(&mut self, key: &K) {
+        if let Some(pos) = self.order.iter().position(|k| k == key) {
+            let key = self.order.remove(pos).expect("position should be in bounds");
+            self.order.push_back(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+
+        // "a" is now more recent than "b"
+        assert_eq!(cache.get(&"a"), Some(1));
+
+        cache.insert("c", 3);
+        assert_eq!(cache.get(&"b"), None);
+        assert_eq!(cache.get(&"a"), Some(1));
+        assert_eq!(cache.get(&"c"), Some(3));
+    }
+
+    #[test]
+    fn zero_capacity() {
+        let mut cache = LruCache::new(0);
+        cache.insert("a", 1);
+        assert_eq!(cache.get(&"a"), None);
+    }
+
+    #[test]
+    fn reinsert_updates_value() {
+        let mut cache = LruCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        cache.insert("a", 3);
+        cache.insert("c", 4);
+
+        assert_eq!(cache.get(&"a"), Some(3));
+        assert_eq!(cache.get(&"b"), None);
+    }
+}
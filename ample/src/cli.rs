@@ -0,0 +1,232 @@
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+
+/// A simple Discord Rich Presence application and LastFM / ListenBrainz scrobbler for Apple Music.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+#[command(group(ArgGroup::new("store").args(["username", "password", "secret", "api_key"]).multiple(true)))]
+pub struct Cli {
+    /// Prompt for your LastFM username and store it in the OS credential manager
+    #[arg(long)]
+    pub username: bool,
+
+    /// Prompt for your LastFM password and store it in the OS credential manager
+    #[arg(long)]
+    pub password: bool,
+
+    /// Prompt for your LastFM API secret and store it in the OS credential manager
+    #[arg(long)]
+    pub secret: bool,
+
+    /// Prompt for your LastFM API key and store it in the OS credential manager
+    #[arg(long)]
+    pub api_key: bool,
+
+    /// Store the credentials given with --username, --password, --secret and --api-key from AMPLE_FM_USERNAME,
+    /// AMPLE_FM_PASSWORD, AMPLE_FM_SECRET and AMPLE_FM_API_KEY instead of prompting for them
+    #[arg(long, requires = "store", conflicts_with = "from_file")]
+    pub from_env: bool,
+
+    /// Store the credential given with --username, --password, --secret or --api-key from the contents of PATH instead of prompting for it.
+    /// A trailing newline is left out
+    #[arg(long, value_name = "PATH", requires = "store")]
+    pub from_file: Option<PathBuf>,
+
+    /// Remove the stored LastFM password (same as `forget password`)
+    #[arg(long)]
+    pub forget_password: bool,
+
+    /// Remove the stored LastFM API secret (same as `forget secret`)
+    #[arg(long)]
+    pub forget_secret: bool,
+
+    /// Remove the stored LastFM session (same as `forget session`)
+    #[arg(long)]
+    pub forget_session: bool,
+
+    /// Remove every stored LastFM credential, including the username (same as `forget all`)
+    #[arg(long)]
+    pub logout: bool,
+
+    /// Update the presence and now playing a single time and exit instead of running until closed.
+    /// Discord removes the activity again once ample exits, so this is mostly useful for checking that everything is set up
+    #[arg(long)]
+    pub once: bool,
+
+    /// Run without the tray icon and log only to stdout, one line per message, instead of to a log file.
+    /// Meant for running under a supervisor or in a container
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Log what would be sent to LastFM and ListenBrainz without actually scrobbling anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Only talk to Discord: nothing is scrobbled, and no covers or LastFM sessions are looked up.
+    /// The presence, tray and local outputs keep working as usual
+    #[arg(long, conflicts_with_all = ["recent", "status"])]
+    pub offline: bool,
+
+    /// Log your last COUNT scrobbles from LastFM and exit, to check that scrobbles are arriving
+    #[arg(long, value_name = "COUNT")]
+    pub recent: Option<u32>,
+
+    /// Check what's playing, the Discord connection and the LastFM credentials once, log how each went and exit.
+    /// Exits with a nonzero code if any of the checks fail
+    #[arg(long)]
+    pub status: bool,
+
+    /// Load the settings from the environment and the .env file, log the value each of them ended up with and exit
+    /// without connecting to anything. Exits with a nonzero code if any of them are invalid
+    #[arg(long, conflicts_with_all = ["once", "recent", "status"])]
+    pub validate_config: bool,
+
+    /// Scrobble the listens in PATH to LastFM and ListenBrainz and exit, e.g. ones from another scrobbler or from while ample wasn't running.
+    /// A ".json" file is a list of objects, anything else is read as CSV with a header. Both have artist, track, album
+    /// and timestamp (in seconds since 1970) fields. With --dry-run the listens are only logged
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["offline", "once", "recent", "status", "validate_config"])]
+    pub import_scrobbles: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Remove stored credentials from the OS credential manager
+    Forget {
+        #[arg(value_enum, required = true)]
+        credentials: Vec<Credential>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq)]
+pub enum Credential {
+    Username,
+    Password,
+    Secret,
+    ApiKey,
+    /// The session token received from LastFM after logging in
+    Session,
+    All,
+}
+
+impl Cli {
+    /// Every credential that should be removed, from both the flags and the `forget` subcommand.
+    /// `All` is expanded so the result only contains specific credentials.
+    pub fn credentials_to_forget(&self) -> Vec<Credential> {
+        let mut requested = Vec::new();
+        if self.forget_password {
+            requested.push(Credential::Password);
+        }
+        if self.forget_secret {
+            requested.push(Credential::Secret);
+        }
+        if self.forget_session {
+            requested.push(Credential::Session);
+        }
+        if self.logout {
+            requested.push(Credential::All);
+        }
+        if let Some(Command::Forget { ref credentials }) = self.command {
+            requested.extend(credentials);
+        }
+
+        let mut forget = Vec::new();
+        for credential in [
+            Credential::Username,
+            Credential::Password,
+            Credential::Secret,
+            Credential::ApiKey,
+            Credential::Session,
+        ] {
+            if requested.contains(&credential) || requested.contains(&Credential::All) {
+                forget.push(credential);
+            }
+        }
+
+        forget
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_flags() {
+        let cli = Cli::parse_from(["ample", "--username", "--secret"]);
+        assert!(cli.username && cli.secret && !cli.password);
+
+        let cli = Cli::parse_from(["ample", "--once"]);
+        assert!(cli.once && !cli.username);
+
+        let cli = Cli::parse_from(["ample", "--headless"]);
+        assert!(cli.headless && !cli.once);
+
+        let cli = Cli::parse_from(["ample", "--dry-run", "--once"]);
+        assert!(cli.dry_run && cli.once);
+
+        let cli = Cli::parse_from(["ample", "--recent", "5"]);
+        assert_eq!(cli.recent, Some(5));
+
+        let cli = Cli::parse_from(["ample", "--status"]);
+        assert!(cli.status && cli.recent.is_none());
+
+        let cli = Cli::parse_from(["ample", "--validate-config"]);
+        assert!(cli.validate_config && !cli.status);
+        assert!(Cli::try_parse_from(["ample", "--validate-config", "--once"]).is_err());
+
+        let cli = Cli::parse_from(["ample", "--import-scrobbles", "scrobbles.csv", "--dry-run"]);
+        assert_eq!(cli.import_scrobbles, Some(PathBuf::from("scrobbles.csv")));
+        assert!(Cli::try_parse_from(["ample", "--import-scrobbles", "scrobbles.csv", "--offline"]).is_err());
+
+        let cli = Cli::parse_from(["ample", "--offline", "--once"]);
+        assert!(cli.offline && cli.once);
+        // Both of these need LastFM
+        assert!(Cli::try_parse_from(["ample", "--offline", "--recent", "5"]).is_err());
+        assert!(Cli::try_parse_from(["ample", "--offline", "--status"]).is_err());
+    }
+
+    #[test]
+    fn credential_sources() {
+        let cli = Cli::parse_from(["ample", "--password", "--secret", "--from-env"]);
+        assert!(cli.password && cli.secret && cli.from_env && cli.from_file.is_none());
+
+        let cli = Cli::parse_from(["ample", "--api-key", "--from-env"]);
+        assert!(cli.api_key && !cli.secret);
+
+        let cli = Cli::parse_from(["ample", "--secret", "--from-file", "/run/secrets/lastfm"]);
+        assert_eq!(cli.from_file, Some(PathBuf::from("/run/secrets/lastfm")));
+
+        // There has to be something to store, and only one place to get it from
+        assert!(Cli::try_parse_from(["ample", "--from-env"]).is_err());
+        assert!(Cli::try_parse_from(["ample", "--from-file", "secret.txt"]).is_err());
+        assert!(Cli::try_parse_from(["ample", "--secret", "--from-env", "--from-file", "secret.txt"]).is_err());
+    }
+
+    #[test]
+    fn forget_flags_and_subcommand() {
+        let cli = Cli::parse_from(["ample", "--forget-secret"]);
+        assert_eq!(cli.credentials_to_forget(), vec![Credential::Secret]);
+
+        let cli = Cli::parse_from(["ample", "--forget-session", "forget", "password"]);
+        assert_eq!(cli.credentials_to_forget(), vec![Credential::Password, Credential::Session]);
+
+        let cli = Cli::parse_from(["ample", "--logout"]);
+        assert_eq!(
+            cli.credentials_to_forget(),
+            vec![
+                Credential::Username,
+                Credential::Password,
+                Credential::Secret,
+                Credential::ApiKey,
+                Credential::Session
+            ]
+        );
+
+        let cli = Cli::parse_from(["ample"]);
+        assert!(cli.credentials_to_forget().is_empty());
+    }
+}
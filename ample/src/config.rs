@@ -0,0 +1,255 @@
+use std::{env, fmt::Display, path::PathBuf, str::FromStr, time::Duration};
+
+use sys_media::{MediaType, consts::APPLE_MUSIC_ID};
+use thiserror::Error;
+
+use crate::{
+    ads,
+    coverart::CoverProvider,
+    lastfm,
+    logging::LogConfig,
+    presence::{self, ReconnectPolicy},
+    scrobbler::ScrobbleRules,
+    template::Template,
+};
+
+const DEFAULT_TRACK_CACHE_SIZE: usize = 64;
+const DEFAULT_DISCORD_MAX_RETRIES: u32 = 5;
+const DEFAULT_DISCORD_RETRY_INTERVAL: u64 = 5 * 60;
+// LastFM asks for no more than 5 requests per second on average
+const DEFAULT_LASTFM_RATE_LIMIT: f64 = 5.0;
+// MusicBrainz turns away requests from apps that don't say who they are and how to reach whoever runs them
+const DEFAULT_USER_AGENT: &str = concat!("ample/", env!("CARGO_PKG_VERSION"), " ( https://github.com/nathanieltooley/ample-rp )");
+
+/// Runtime settings for ample. Each one is read from an environment variable,
+/// which means they can also be set in the .env file next to the executable.
+#[derive(Debug, Clone)]
+pub struct AmpleConfig {
+    /// Max number of LastFM track lookups kept in memory (AMPLE_TRACK_CACHE_SIZE). 0 disables the cache.
+    pub track_cache_size: usize,
+    /// Root url of the LastFM compatible API to scrobble to (AMPLE_FM_API_ROOT), e.g. https://libre.fm/2.0/ for Libre.fm.
+    pub lastfm_api_root: String,
+    /// Most LastFM requests to send per second (AMPLE_FM_RATE_LIMIT), short bursts are let through. 0 turns the limit off.
+    pub lastfm_rate_limit: f64,
+    /// Check that the stored LastFM session still works when ample starts (AMPLE_FM_CHECK_SESSION).
+    pub check_lastfm_session: bool,
+    /// Media types that get scrobbled (AMPLE_SCROBBLE_MEDIA_TYPES, comma separated, e.g. "music,unknown"). Defaults to only music.
+    pub scrobble_media_types: Vec<MediaType>,
+    /// How long a track has to be (AMPLE_SCROBBLE_MIN_LENGTH in seconds) and how much of it has to be played
+    /// (AMPLE_SCROBBLE_FRACTION, or AMPLE_SCROBBLE_AFTER seconds, whichever comes first) before it's scrobbled.
+    pub scrobble_rules: ScrobbleRules,
+    /// Wait for the next track to start before scrobbling one that met `scrobble_rules` (AMPLE_SCROBBLE_ON_COMPLETION),
+    /// instead of scrobbling it while it's still playing.
+    pub scrobble_on_completion: bool,
+    /// How long a new track has to keep playing before it's shown and sent as now playing (AMPLE_TRACK_DEBOUNCE in seconds),
+    /// so skipping through tracks doesn't flood Discord and the scrobblers. Zero turns this off.
+    pub track_debounce: Duration,
+    /// How long nothing has to be playing before the presence is cleared (AMPLE_IDLE_TIMEOUT in seconds), so the gaps
+    /// between tracks don't make it flicker. Zero clears it right away. Paused tracks don't count as idle.
+    pub idle_timeout: Duration,
+    /// Templates for the activity's first (AMPLE_DETAILS_FORMAT) and second (AMPLE_STATE_FORMAT) line, see [crate::template::Template].
+    /// Checked once the presence starts, invalid templates are replaced by the defaults there.
+    pub details_format: String,
+    pub state_format: String,
+    /// Discord asset key or image url shown when a track has no cover (AMPLE_DEFAULT_COVER). Empty shows no image.
+    pub default_cover: String,
+    /// Players whose tracks are shown in Discord (AMPLE_PRESENCE_PLAYERS) and scrobbled (AMPLE_SCROBBLE_PLAYERS), comma separated.
+    /// Both default to only Apple Music, an empty list allows every player. See [player_allowed].
+    pub presence_players: Vec<String>,
+    pub scrobble_players: Vec<String>,
+    /// Where covers are looked up, in order (AMPLE_COVER_PROVIDERS, comma separated). Names are checked once the LastFM thread
+    /// starts, see [crate::coverart::parse_providers]. An empty list doesn't look up covers at all.
+    pub cover_providers: Vec<String>,
+    /// Port on 127.0.0.1 to serve the covers players share on (AMPLE_THUMBNAIL_PORT), see [crate::thumbnail]. 0 doesn't serve them.
+    pub thumbnail_port: u16,
+    /// Url Discord can reach the cover server at (AMPLE_THUMBNAIL_URL), when it's forwarded. Empty uses the server's own address.
+    pub thumbnail_url: String,
+    /// Image host to upload the covers players share to (AMPLE_COVER_UPLOAD_URL), see [crate::coverupload]. Empty doesn't upload them.
+    /// The url of an uploaded cover is read from the AMPLE_COVER_UPLOAD_FIELD of the host's answer, e.g. "data.link" for imgur.
+    pub cover_upload_url: String,
+    pub cover_upload_field: String,
+    /// Sent as the User-Agent of every request to LastFM, ListenBrainz, MusicBrainz and the image host (AMPLE_USER_AGENT).
+    /// Defaults to ample's name, version and repository.
+    pub user_agent: String,
+    /// Check that covers can be fetched and are an image type Discord shows (AMPLE_CHECK_COVERS) before using them.
+    /// Covers that fail the check aren't shown.
+    pub check_covers: bool,
+    /// Keep showing paused tracks in the presence (AMPLE_SHOW_PAUSED). When false the activity is cleared on pause instead.
+    pub show_paused: bool,
+    /// Titles that mark a track as an ad (AMPLE_AD_TITLES, comma separated). Ads are never scrobbled, see [ads::is_advertisement].
+    pub ad_titles: Vec<String>,
+    /// Clear the presence while an ad plays (AMPLE_CLEAR_ADS). When false the ad is shown like any other track.
+    pub clear_ads: bool,
+    /// Show the tray icon (AMPLE_TRAY). Without it ample can only be closed with Ctrl-C or by ending the process.
+    pub tray: bool,
+    /// Show a desktop notification when a new song starts (AMPLE_NOTIFICATIONS). Needs the "notifications" feature.
+    pub notifications: bool,
+    /// Keep now_playing.json in the config folder up to date with what's playing (AMPLE_NOW_PLAYING_FILE), see [crate::nowplaying].
+    pub now_playing_file: bool,
+    /// Unix socket or Windows named pipe to send an event to whenever what's playing changes (AMPLE_EVENT_SOCKET).
+    pub event_socket: Option<PathBuf>,
+    /// How many times to try reconnecting to Discord (AMPLE_DISCORD_MAX_RETRIES) before only trying every
+    /// AMPLE_DISCORD_RETRY_INTERVAL seconds.
+    pub discord_reconnect: ReconnectPolicy,
+    /// Log file limits (AMPLE_LOG_MAX_FILE_SIZE in bytes and AMPLE_LOG_MAX_FILES) and
+    /// how they're rotated (AMPLE_LOG_ROTATION, "size" or "daily"), as well as the file's format (AMPLE_LOG_FORMAT, "text" or "json").
+    pub log: LogConfig,
+}
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    // static lifetime of key since it should be a string literal
+    #[error("Error obtaining environment variable, {0}, because {1}")]
+    Env(&'static str, env::VarError),
+    #[error("Invalid value for {0}: {1}")]
+    Invalid(&'static str, String),
+}
+
+impl AmpleConfig {
+    pub fn from_env() -> Result<AmpleConfig, ConfigError> {
+        let log_defaults = LogConfig::default();
+        let log = LogConfig {
+            max_file_size: parse_env("AMPLE_LOG_MAX_FILE_SIZE", log_defaults.max_file_size)?,
+            max_files: parse_env("AMPLE_LOG_MAX_FILES", log_defaults.max_files)?,
+            rotation: parse_env("AMPLE_LOG_ROTATION", log_defaults.rotation)?,
+            format: parse_env("AMPLE_LOG_FORMAT", log_defaults.format)?,
+        };
+
+        if log.max_file_size == 0 {
+            return Err(ConfigError::Invalid("AMPLE_LOG_MAX_FILE_SIZE", "must be greater than 0".to_owned()));
+        }
+
+        if log.max_files < 1 {
+            return Err(ConfigError::Invalid("AMPLE_LOG_MAX_FILES", "must be at least 1".to_owned()));
+        }
+
+        let rules_defaults = ScrobbleRules::default();
+        let scrobble_rules = ScrobbleRules {
+            min_length: Duration::from_secs(parse_env("AMPLE_SCROBBLE_MIN_LENGTH", rules_defaults.min_length.as_secs())?),
+            fraction: parse_env("AMPLE_SCROBBLE_FRACTION", rules_defaults.fraction)?,
+            max_wait: Duration::from_secs(parse_env("AMPLE_SCROBBLE_AFTER", rules_defaults.max_wait.as_secs())?),
+        };
+
+        if !(scrobble_rules.fraction > 0.0 && scrobble_rules.fraction <= 1.0) {
+            return Err(ConfigError::Invalid(
+                "AMPLE_SCROBBLE_FRACTION",
+                "must be greater than 0 and at most 1".to_owned(),
+            ));
+        }
+
+        let lastfm_rate_limit = parse_env("AMPLE_FM_RATE_LIMIT", DEFAULT_LASTFM_RATE_LIMIT)?;
+        if !(lastfm_rate_limit >= 0.0 && lastfm_rate_limit.is_finite()) {
+            return Err(ConfigError::Invalid("AMPLE_FM_RATE_LIMIT", "must be a number of at least 0".to_owned()));
+        }
+
+        let user_agent = parse_env("AMPLE_USER_AGENT", String::new())?;
+
+        Ok(AmpleConfig {
+            track_cache_size: parse_env("AMPLE_TRACK_CACHE_SIZE", DEFAULT_TRACK_CACHE_SIZE)?,
+            lastfm_api_root: parse_env("AMPLE_FM_API_ROOT", lastfm::DEFAULT_API_ROOT.to_owned())?,
+            lastfm_rate_limit,
+            check_lastfm_session: parse_env("AMPLE_FM_CHECK_SESSION", true)?,
+            scrobble_media_types: parse_env_list("AMPLE_SCROBBLE_MEDIA_TYPES", vec![MediaType::Music])?,
+            scrobble_rules,
+            scrobble_on_completion: parse_env("AMPLE_SCROBBLE_ON_COMPLETION", false)?,
+            track_debounce: Duration::from_secs(parse_env("AMPLE_TRACK_DEBOUNCE", 0)?),
+            idle_timeout: Duration::from_secs(parse_env("AMPLE_IDLE_TIMEOUT", 0)?),
+            details_format: parse_env("AMPLE_DETAILS_FORMAT", presence::DEFAULT_DETAILS_FORMAT.to_owned())?,
+            state_format: parse_env("AMPLE_STATE_FORMAT", presence::DEFAULT_STATE_FORMAT.to_owned())?,
+            default_cover: parse_env("AMPLE_DEFAULT_COVER", String::new())?,
+            presence_players: parse_env_list("AMPLE_PRESENCE_PLAYERS", vec![APPLE_MUSIC_ID.to_owned()])?,
+            scrobble_players: parse_env_list("AMPLE_SCROBBLE_PLAYERS", vec![APPLE_MUSIC_ID.to_owned()])?,
+            cover_providers: parse_env_list("AMPLE_COVER_PROVIDERS", ["embedded", "lastfm", "coverart"].map(str::to_owned).to_vec())?,
+            thumbnail_port: parse_env("AMPLE_THUMBNAIL_PORT", 0)?,
+            thumbnail_url: parse_env("AMPLE_THUMBNAIL_URL", String::new())?,
+            cover_upload_url: parse_env("AMPLE_COVER_UPLOAD_URL", String::new())?,
+            cover_upload_field: parse_env("AMPLE_COVER_UPLOAD_FIELD", "data.link".to_owned())?,
+            user_agent: if user_agent.is_empty() {
+                DEFAULT_USER_AGENT.to_owned()
+            } else {
+                user_agent
+            },
+            check_covers: parse_env("AMPLE_CHECK_COVERS", false)?,
+            show_paused: parse_env("AMPLE_SHOW_PAUSED", true)?,
+            ad_titles: parse_env_list("AMPLE_AD_TITLES", ads::DEFAULT_AD_TITLES.map(str::to_owned).to_vec())?,
+            clear_ads: parse_env("AMPLE_CLEAR_ADS", true)?,
+            tray: parse_env("AMPLE_TRAY", true)?,
+            notifications: parse_env("AMPLE_NOTIFICATIONS", false)?,
+            discord_reconnect: ReconnectPolicy {
+                max_retries: parse_env("AMPLE_DISCORD_MAX_RETRIES", DEFAULT_DISCORD_MAX_RETRIES)?,
+                give_up_interval: Duration::from_secs(parse_env("AMPLE_DISCORD_RETRY_INTERVAL", DEFAULT_DISCORD_RETRY_INTERVAL)?),
+            },
+            now_playing_file: parse_env("AMPLE_NOW_PLAYING_FILE", false)?,
+            event_socket: env::var_os("AMPLE_EVENT_SOCKET").filter(|path| !path.is_empty()).map(PathBuf::from),
+            log,
+        })
+    }
+}
+
+impl AmpleConfig {
+    /// Settings that are only checked once they're used, where ample warns and carries on with a default instead of
+    /// refusing to start. Lets --validate-config report them up front.
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (key, template) in [("AMPLE_DETAILS_FORMAT", &self.details_format), ("AMPLE_STATE_FORMAT", &self.state_format)] {
+            if let Err(err) = template.parse::<Template>() {
+                problems.push(format!("Invalid {key} {template:?}: {err}"));
+            }
+        }
+
+        for provider in &self.cover_providers {
+            if let Err(err) = provider.parse::<CoverProvider>() {
+                problems.push(format!("AMPLE_COVER_PROVIDERS has an {err}"));
+            }
+        }
+
+        if !self.thumbnail_url.is_empty() && self.thumbnail_port == 0 {
+            problems.push("AMPLE_THUMBNAIL_URL is set but AMPLE_THUMBNAIL_PORT isn't, so no covers are served".to_owned());
+        }
+
+        if !self.cover_upload_url.is_empty() && self.cover_upload_field.trim().is_empty() {
+            problems.push("AMPLE_COVER_UPLOAD_FIELD is empty, so uploaded covers can't be found in the host's answer".to_owned());
+        }
+
+        problems
+    }
+}
+
+/// Whether `player_name` is one of `players`, ignoring case. An empty list allows every player.
+pub fn player_allowed(players: &[String], player_name: &str) -> bool {
+    players.is_empty() || players.iter().any(|player| player.eq_ignore_ascii_case(player_name))
+}
+
+/// Parses the environment variable `key`, using `default` when it is not set.
+fn parse_env<T>(key: &'static str, default: T) -> Result<T, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .trim()
+            .parse()
+            .map_err(|err| ConfigError::Invalid(key, format!("{value:?} ({err})"))),
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(err) => Err(ConfigError::Env(key, err)),
+    }
+}
+
+/// Parses the environment variable `key` as a comma separated list, using `default` when it is not set.
+fn parse_env_list<T>(key: &'static str, default: Vec<T>) -> Result<Vec<T>, ConfigError>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    match env::var(key) {
+        Ok(value) => value
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(|item| item.parse().map_err(|err| ConfigError::Invalid(key, format!("{item:?} ({err})"))))
+            .collect(),
+        Err(env::VarError::NotPresent) => Ok(default),
+        Err(err) => Err(ConfigError::Env(key, err)),
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Display},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use log::{debug, warn};
+use serde::Deserialize;
+use ureq::Agent;
+
+use crate::uri;
+
+const MUSICBRAINZ_ROOT: &str = "https://musicbrainz.org/ws/2";
+const COVER_ART_ROOT: &str = "https://coverartarchive.org";
+/// Image types Discord shows as an activity's large image
+const DISPLAYABLE_IMAGE_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// Where covers are looked up, tried in the order given by AMPLE_COVER_PROVIDERS.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoverProvider {
+    /// The cover the player itself shared, served by [crate::thumbnail::ThumbnailServer]
+    Embedded,
+    /// The album art the scrobblers have for the track or its album
+    LastFm,
+    /// The Cover Art Archive, see [CoverArtArchive]
+    CoverArt,
+}
+
+impl FromStr for CoverProvider {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "embedded" => Ok(CoverProvider::Embedded),
+            "lastfm" => Ok(CoverProvider::LastFm),
+            "coverart" => Ok(CoverProvider::CoverArt),
+            _ => Err(format!("unknown cover provider {s:?}, expected \"embedded\", \"lastfm\" or \"coverart\"")),
+        }
+    }
+}
+
+impl Display for CoverProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CoverProvider::Embedded => write!(f, "embedded"),
+            CoverProvider::LastFm => write!(f, "lastfm"),
+            CoverProvider::CoverArt => write!(f, "coverart"),
+        }
+    }
+}
+
+/// Turns the configured provider names into providers, skipping and warning about the ones that aren't known.
+/// A provider that's listed twice is only tried the first time.
+pub fn parse_providers(names: &[String]) -> Vec<CoverProvider> {
+    let mut providers = Vec::new();
+    for name in names {
+        match name.parse() {
+            Ok(provider) if !providers.contains(&provider) => providers.push(provider),
+            Ok(_) => {}
+            Err(err) => warn!("Ignoring {err}"),
+        }
+    }
+
+    providers
+}
+
+/// Looks up album covers from the Cover Art Archive, using MusicBrainz to find the release.
+#[derive(Debug, Clone)]
+pub struct CoverArtArchive {
+    client: Agent,
+    // (artist, album) pairs that are known to have no cover so they aren't looked up again
+    missing: Arc<Mutex<HashSet<(String, String)>>>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReleaseSearchResponse {
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Release {
+    id: String,
+}
+
+impl CoverArtArchive {
+    pub fn new(client: Agent) -> CoverArtArchive {
+        CoverArtArchive {
+            client,
+            missing: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Gets the url of the front cover for the given album.
+    /// Returns `Ok(None)` if MusicBrainz doesn't know the release or the release has no cover.
+    pub fn get_cover_url(&self, artist: &str, album: &str) -> Result<Option<String>, ureq::Error> {
+        let key = (artist.to_owned(), album.to_owned());
+        if self.missing.lock().unwrap().contains(&key) {
+            debug!("Already know there is no cover art for {album} by {artist}");
+            return Ok(None);
+        }
+
+        let cover = self.find_cover(artist, album)?;
+        if cover.is_none() {
+            self.missing.lock().unwrap().insert(key);
+        }
+
+        Ok(cover)
+    }
+
+    fn find_cover(&self, artist: &str, album: &str) -> Result<Option<String>, ureq::Error> {
+        let Some(mbid) = self.find_release_mbid(artist, album)? else {
+            return Ok(None);
+        };
+
+        self.get_release_cover_url(&mbid)
+    }
+
+    /// Gets the url of the front cover for the release with the MusicBrainz id `mbid`, skipping the search.
+    pub fn get_release_cover_url(&self, mbid: &str) -> Result<Option<String>, ureq::Error> {
+        let url = format!("{COVER_ART_ROOT}/release/{mbid}/front");
+        // The archive redirects to the image if there is one and 404s otherwise
+        let rep = self.client.head(&url).call()?;
+        debug!("{url}: {}", rep.status());
+
+        if rep.status().is_success() {
+            Ok(Some(url))
+        } else if rep.status().as_u16() == 404 {
+            Ok(None)
+        } else {
+            Err(ureq::Error::StatusCode(rep.status().as_u16()))
+        }
+    }
+
+    fn find_release_mbid(&self, artist: &str, album: &str) -> Result<Option<String>, ureq::Error> {
+        let query = format!("release:\"{}\" AND artist:\"{}\"", escape_query(album), escape_query(artist));
+        let uri = format!("{MUSICBRAINZ_ROOT}/release/?query={}&limit=1&fmt=json", uri::percent_encode(&query));
+        debug!("{uri}");
+
+        let mut rep = self.client.get(&uri).call()?;
+        let body = rep.body_mut().read_to_string()?;
+
+        debug!("{body}");
+
+        if rep.status().is_client_error() || rep.status().is_server_error() {
+            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+        }
+
+        let search: ReleaseSearchResponse = serde_json::from_str(&body)?;
+
+        Ok(search.releases.into_iter().next().map(|release| release.id))
+    }
+}
+
+/// Checks that the cover at `url` can be fetched and is an image Discord can show, since Discord
+/// leaves out the cover entirely when it can't use the url. Unreachable urls count as not displayable.
+pub fn is_displayable_cover(client: &Agent, url: &str) -> bool {
+    let rep = match client.head(url).call() {
+        Ok(rep) => rep,
+        Err(err) => {
+            debug!("Failed to check cover {url}: {err}");
+            return false;
+        }
+    };
+
+    let content_type = rep
+        .headers()
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    debug!("{url}: {} {content_type}", rep.status());
+
+    rep.status().is_success() && is_displayable_type(content_type)
+}
+
+/// Whether a Content-Type header is one of [DISPLAYABLE_IMAGE_TYPES], ignoring parameters like charset.
+fn is_displayable_type(content_type: &str) -> bool {
+    let mime = content_type.split(';').next().unwrap_or_default().trim();
+    DISPLAYABLE_IMAGE_TYPES.iter().any(|image_type| image_type.eq_ignore_ascii_case(mime))
+}
+
+/// Escapes characters that would end a quoted term in a MusicBrainz (Lucene) search query.
+fn escape_query(term: &str) -> String {
+    term.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cover_providers() {
+        let names = ["CoverArt", "spotify", "lastfm", "coverart", "embedded"].map(str::to_owned);
+        assert_eq!(
+            parse_providers(&names),
+            vec![CoverProvider::CoverArt, CoverProvider::LastFm, CoverProvider::Embedded]
+        );
+        assert_eq!(parse_providers(&[]), vec![]);
+    }
+
+    #[test]
+    fn displayable_types() {
+        assert!(is_displayable_type("image/png"));
+        assert!(is_displayable_type("IMAGE/JPEG; charset=binary"));
+        assert!(is_displayable_type("image/webp"));
+
+        assert!(!is_displayable_type("image/avif"));
+        assert!(!is_displayable_type("text/html; charset=utf-8"));
+        assert!(!is_displayable_type(""));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+};
+
+use crossbeam::channel::{self, Sender};
+use log::*;
+use serde_json::Value;
+use sys_media::{MediaInfo, Thumbnail};
+use ureq::Agent;
+
+use crate::cache::LruCache;
+
+// Urls of uploaded covers kept around, so an album's cover is only uploaded once while it's being listened to
+const UPLOADED_CACHE_SIZE: usize = 64;
+
+/// Uploads the covers players share to an image host in the background, for when Discord can't reach the
+/// [crate::thumbnail::ThumbnailServer]. Meant for imgur-like APIs: the image is POSTed as the body and the host
+/// answers with JSON that has the image's url in it.
+pub struct CoverUploader {
+    tx: Sender<(MediaInfo, Thumbnail)>,
+    // Keyed by [cover_key]
+    uploaded: Arc<Mutex<LruCache<(String, String), String>>>,
+}
+
+struct ImageHost {
+    client: Agent,
+    endpoint: String,
+    // Sent as the Authorization header as-is, e.g. "Client-ID ..." for imgur
+    token: Option<String>,
+    // Where the url is in the answer, e.g. "data.link"
+    url_field: String,
+}
+
+impl CoverUploader {
+    /// Starts the thread that uploads covers to `endpoint`. `on_uploaded` is called from that thread with the url of every
+    /// cover once it's uploaded.
+    pub fn spawn(
+        client: Agent,
+        endpoint: String,
+        token: Option<String>,
+        url_field: String,
+        on_uploaded: impl Fn(String) + Send + 'static,
+    ) -> CoverUploader {
+        let (tx, rx) = channel::unbounded::<(MediaInfo, Thumbnail)>();
+        let uploaded = Arc::new(Mutex::new(LruCache::new(UPLOADED_CACHE_SIZE)));
+        let host = ImageHost {
+            client,
+            endpoint,
+            token,
+            url_field,
+        };
+
+        let cache = Arc::clone(&uploaded);
+        thread::spawn(move || {
+            for (info, thumbnail) in rx {
+                let key = cover_key(&info);
+                // Songs from the same album can be queued before the first upload finishes
+                if let Some(cover_url) = cache.lock().unwrap().get(&key) {
+                    on_uploaded(cover_url);
+                    continue;
+                }
+
+                match host.upload(&thumbnail) {
+                    Ok(Some(cover_url)) => {
+                        info!("Uploaded the cover for {} to {cover_url}", info.song_name);
+                        cache.lock().unwrap().insert(key, cover_url.clone());
+                        on_uploaded(cover_url);
+                    }
+                    Ok(None) => error!("The image host's answer has no {:?} for the cover's url", host.url_field),
+                    Err(err) => error!("Failed to upload the cover for {}: {err}", info.song_name),
+                }
+            }
+        });
+
+        CoverUploader { tx, uploaded }
+    }
+
+    /// Where the cover for `info` was uploaded to before, if it was.
+    pub fn uploaded_url(&self, info: &MediaInfo) -> Option<String> {
+        self.uploaded.lock().unwrap().get(&cover_key(info))
+    }
+
+    /// Uploads `thumbnail`, the cover for `info`, in the background.
+    pub fn upload(&self, info: MediaInfo, thumbnail: Thumbnail) {
+        // The thread only stops if it panicked
+        let _ = self.tx.send((info, thumbnail));
+    }
+}
+
+impl ImageHost {
+    fn upload(&self, thumbnail: &Thumbnail) -> Result<Option<String>, ureq::Error> {
+        let mut request = self.client.post(&self.endpoint).header("Content-Type", &thumbnail.content_type);
+        if let Some(ref token) = self.token {
+            request = request.header("Authorization", token);
+        }
+
+        let mut rep = request.send(&thumbnail.data[..])?;
+        let body = rep.body_mut().read_to_string()?;
+        debug!("{body}");
+
+        if rep.status().is_client_error() || rep.status().is_server_error() {
+            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+        }
+
+        let answer: Value = serde_json::from_str(&body)?;
+        Ok(url_at(&answer, &self.url_field))
+    }
+}
+
+/// Tracks of the same album share a cover, so only tracks without an album are told apart by their name.
+fn cover_key(info: &MediaInfo) -> (String, String) {
+    let title = if info.album_name.is_empty() { &info.song_name } else { &info.album_name };
+    (info.artist_name.clone(), title.clone())
+}
+
+/// The string at `path` in `answer`, with the keys of nested objects separated by dots.
+fn url_at(answer: &Value, path: &str) -> Option<String> {
+    path.split('.')
+        .try_fold(answer, |value, key| value.get(key))
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answer_urls() {
+        let answer: Value = serde_json::from_str(r#"{"data":{"id":"abc","link":"https://i.imgur.com/abc.png"},"success":true}"#).unwrap();
+        assert_eq!(url_at(&answer, "data.link").as_deref(), Some("https://i.imgur.com/abc.png"));
+        assert_eq!(url_at(&answer, "link"), None);
+        assert_eq!(url_at(&answer, "success"), None);
+
+        let answer: Value = serde_json::from_str(r#"{"url":"https://example.com/abc.png"}"#).unwrap();
+        assert_eq!(url_at(&answer, "url").as_deref(), Some("https://example.com/abc.png"));
+    }
+
+    #[test]
+    fn cover_keys() {
+        let info = MediaInfo {
+            song_name: "7".to_owned(),
+            artist_name: "Catfish and the Bottlemen".to_owned(),
+            album_name: "The Balcony".to_owned(),
+            ..Default::default()
+        };
+        let other_song = MediaInfo {
+            song_name: "Kathleen".to_owned(),
+            ..info.clone()
+        };
+        assert_eq!(cover_key(&info), cover_key(&other_song));
+
+        let single = MediaInfo {
+            album_name: String::new(),
+            ..info.clone()
+        };
+        assert_eq!(cover_key(&single), ("Catfish and the Bottlemen".to_owned(), "7".to_owned()));
+    }
+}
@@ -0,0 +1,205 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    ChaCha20Poly1305, Key, KeyInit, Nonce,
+    aead::{Aead, AeadCore, OsRng, rand_core::RngCore},
+};
+use thiserror::Error;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// Ample's own encrypted store for credentials, for machines without a credential manager (e.g. a headless linux
+/// server without a Secret Service). The whole store is a single file: a salt and nonce followed by the encrypted
+/// JSON of every entry.
+///
+/// The key is derived from AMPLE_CREDENTIALS_PASSPHRASE, or when that isn't set, from a random key kept in a file
+/// next to the store that only the current user can read.
+pub struct CredentialFile {
+    path: PathBuf,
+    secret: Vec<u8>,
+}
+
+#[derive(Error, Debug)]
+pub enum CredentialFileError {
+    #[error("Failed to access the credentials file: {0}")]
+    Io(#[from] io::Error),
+    #[error("Failed to decrypt the credentials file, AMPLE_CREDENTIALS_PASSPHRASE or the key file may have changed")]
+    Decrypt,
+    #[error("The credentials file is corrupted")]
+    Corrupted,
+    #[error("Failed to derive the credentials file's key: {0}")]
+    Key(String),
+}
+
+impl CredentialFile {
+    pub fn new(path: PathBuf, secret: Vec<u8>) -> CredentialFile {
+        CredentialFile { path, secret }
+    }
+
+    /// "credentials" in ample's config folder, e.g. "~/.config/ample/credentials" on linux, locked as described on
+    /// [CredentialFile]. Creates the key file if it's needed and there isn't one yet.
+    pub fn open_default() -> Result<CredentialFile, CredentialFileError> {
+        let dir = directories::ProjectDirs::from("", "", crate::APP_NAME)
+            .map(|dirs| dirs.config_dir().to_owned())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+
+        let secret = match env::var("AMPLE_CREDENTIALS_PASSPHRASE") {
+            Ok(passphrase) if !passphrase.is_empty() => passphrase.into_bytes(),
+            _ => machine_key(&dir.join("credentials.key"))?,
+        };
+
+        Ok(CredentialFile::new(dir.join("credentials"), secret))
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>, CredentialFileError> {
+        Ok(self.load()?.1.remove(name))
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<(), CredentialFileError> {
+        let (salt, mut entries) = self.load()?;
+        entries.insert(name.to_owned(), value.to_owned());
+        self.save(&salt, &entries)
+    }
+
+    /// Returns false if there was no entry called `name`.
+    pub fn delete(&self, name: &str) -> Result<bool, CredentialFileError> {
+        let (salt, mut entries) = self.load()?;
+        if entries.remove(name).is_none() {
+            return Ok(false);
+        }
+
+        self.save(&salt, &entries)?;
+        Ok(true)
+    }
+
+    /// The salt of the file and every entry in it. A missing file is an empty store with a new salt.
+    fn load(&self) -> Result<([u8; SALT_LEN], BTreeMap<String, String>), CredentialFileError> {
+        let contents = match fs::read(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let mut salt = [0; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                return Ok((salt, BTreeMap::new()));
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        if contents.len() < SALT_LEN + NONCE_LEN {
+            return Err(CredentialFileError::Corrupted);
+        }
+
+        let (salt, rest) = contents.split_at(SALT_LEN);
+        let (nonce, encrypted) = rest.split_at(NONCE_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into().expect("split at SALT_LEN");
+
+        let json = self
+            .cipher(&salt)?
+            .decrypt(Nonce::from_slice(nonce), encrypted)
+            .map_err(|_| CredentialFileError::Decrypt)?;
+        let entries = serde_json::from_slice(&json).map_err(|_| CredentialFileError::Corrupted)?;
+
+        Ok((salt, entries))
+    }
+
+    fn save(&self, salt: &[u8; SALT_LEN], entries: &BTreeMap<String, String>) -> Result<(), CredentialFileError> {
+        // Serializing a map of strings can't fail
+        let json = serde_json::to_vec(entries).unwrap_or_default();
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let encrypted = self
+            .cipher(salt)?
+            .encrypt(&nonce, &json[..])
+            .map_err(|err| CredentialFileError::Key(err.to_string()))?;
+
+        let mut contents = Vec::with_capacity(SALT_LEN + NONCE_LEN + encrypted.len());
+        contents.extend_from_slice(salt);
+        contents.extend_from_slice(&nonce);
+        contents.extend_from_slice(&encrypted);
+
+        // Renamed over the store so that it's never left half written
+        let temp_path = self.path.with_extension("tmp");
+        write_private(&temp_path, &contents)?;
+        fs::rename(temp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn cipher(&self, salt: &[u8; SALT_LEN]) -> Result<ChaCha20Poly1305, CredentialFileError> {
+        let mut key = [0; KEY_LEN];
+        Argon2::default()
+            .hash_password_into(&self.secret, salt, &mut key)
+            .map_err(|err| CredentialFileError::Key(err.to_string()))?;
+
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+}
+
+/// The random key in `path`, which is created the first time it's needed.
+fn machine_key(path: &Path) -> io::Result<Vec<u8>> {
+    match fs::read(path) {
+        Ok(key) => Ok(key),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            let mut key = vec![0; KEY_LEN];
+            OsRng.fill_bytes(&mut key);
+            write_private(path, &key)?;
+            Ok(key)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Writes `contents` to `path`, which only the current user can read on unix. Windows has no such mode,
+/// the config folder is already only readable by its user there.
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    #[test]
+    fn stores_entries() {
+        let dir = env::temp_dir().join(format!("ample-credentials-{}", process::id()));
+        let path = dir.join("credentials");
+
+        let store = CredentialFile::new(path.clone(), b"correct horse".to_vec());
+        assert_eq!(store.get("amplePassword").unwrap(), None);
+
+        store.set("amplePassword", "hunter2").unwrap();
+        store.set("ampleSecret", "abc123").unwrap();
+        assert_eq!(store.get("amplePassword").unwrap().as_deref(), Some("hunter2"));
+        // Nothing is stored in plain text
+        assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("hunter2"));
+
+        assert!(store.delete("amplePassword").unwrap());
+        assert!(!store.delete("amplePassword").unwrap());
+        assert_eq!(store.get("ampleSecret").unwrap().as_deref(), Some("abc123"));
+
+        let wrong_passphrase = CredentialFile::new(path, b"battery staple".to_vec());
+        assert!(matches!(wrong_passphrase.get("ampleSecret"), Err(CredentialFileError::Decrypt)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
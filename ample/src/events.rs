@@ -0,0 +1,119 @@
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::*;
+use serde::Serialize;
+use sys_media::MediaInfo;
+
+/// Sends a JSON line to a local socket (a named pipe on Windows) whenever what's playing changes,
+/// so other programs can react to it. Whatever is listening has to create the socket, ample only connects to it.
+pub struct EventSink {
+    path: PathBuf,
+    // Last line sent, so the same state isn't sent every tick
+    last_event: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MediaEvent<'a> {
+    artist: Option<&'a str>,
+    song: Option<&'a str>,
+    album: Option<&'a str>,
+    status: String,
+    shuffle: Option<bool>,
+    repeat: Option<String>,
+}
+
+impl EventSink {
+    pub fn new(path: PathBuf) -> EventSink {
+        EventSink { path, last_event: None }
+    }
+
+    /// Sends the current media, or a "stopped" event when nothing is playing. Does nothing if it's the same as last time.
+    pub fn send(&mut self, media_info: Option<&MediaInfo>) {
+        let event = event_line(media_info);
+        if self.last_event.as_ref() == Some(&event) {
+            return;
+        }
+
+        match self.write_line(&event) {
+            Ok(()) => {
+                debug!("Sent event to {}: {event}", self.path.display());
+                self.last_event = Some(event);
+            }
+            // Most likely nothing is listening right now, which is fine. It's sent again next time
+            Err(err) => debug!("Failed to send event to {}: {err}", self.path.display()),
+        }
+    }
+
+    /// Connects for every event so that listeners can come and go.
+    fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut stream = connect(&self.path)?;
+        stream.write_all(line.as_bytes())?;
+        stream.write_all(b"\n")?;
+        stream.flush()
+    }
+}
+
+fn event_line(media_info: Option<&MediaInfo>) -> String {
+    let event = match media_info {
+        Some(info) => MediaEvent {
+            artist: Some(&info.artist_name),
+            song: Some(&info.song_name),
+            album: Some(&info.album_name),
+            status: info.status.to_string(),
+            shuffle: info.shuffle,
+            repeat: info.repeat.map(|repeat| repeat.to_string()),
+        },
+        None => MediaEvent {
+            artist: None,
+            song: None,
+            album: None,
+            status: "stopped".to_owned(),
+            shuffle: None,
+            repeat: None,
+        },
+    };
+
+    // Serializing a struct of strings can't fail
+    serde_json::to_string(&event).unwrap_or_default()
+}
+
+#[cfg(unix)]
+fn connect(path: &Path) -> io::Result<impl Write> {
+    std::os::unix::net::UnixStream::connect(path)
+}
+
+/// Named pipes are opened like files, e.g. \\.\pipe\ample
+#[cfg(windows)]
+fn connect(path: &Path) -> io::Result<impl Write> {
+    std::fs::OpenOptions::new().write(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use sys_media::{MediaStatus, RepeatMode};
+
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn event_lines() {
+        let info = MediaInfo {
+            status: MediaStatus::Paused,
+            shuffle: Some(true),
+            repeat: Some(RepeatMode::List),
+            ..testing::media()
+        };
+
+        assert_eq!(
+            event_line(Some(&info)),
+            r#"{"artist":"Catfish and the Bottlemen","song":"7","album":"The Balcony","status":"paused","shuffle":true,"repeat":"list"}"#
+        );
+        assert_eq!(
+            event_line(None),
+            r#"{"artist":null,"song":null,"album":null,"status":"stopped","shuffle":null,"repeat":null}"#
+        );
+    }
+}
@@ -0,0 +1,174 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+/// A listen from a file of scrobble history, see [read_listens].
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct Listen {
+    pub artist: String,
+    pub track: String,
+    #[serde(default)]
+    pub album: Option<String>,
+    /// When the track started playing, in seconds since the epoch
+    pub timestamp: u64,
+}
+
+#[derive(Error, Debug)]
+pub enum ImportError {
+    #[error("Failed to read {0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("The CSV header needs artist, track and timestamp columns, got {0:?}")]
+    Header(String),
+    #[error("Invalid listen on line {0}: {1}")]
+    Line(usize, String),
+}
+
+/// Reads the listens in `path`, oldest first. Files ending in ".json" are a list of objects with the fields of [Listen],
+/// anything else is read as CSV with one listen per line and a header naming the columns, in any order.
+pub fn read_listens(path: &Path) -> Result<Vec<Listen>, ImportError> {
+    let contents = fs::read_to_string(path).map_err(|err| ImportError::Io(path.to_owned(), err))?;
+    let is_json = path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+
+    let mut listens = if is_json {
+        serde_json::from_str(&contents)?
+    } else {
+        parse_csv(&contents)?
+    };
+    for listen in &mut listens {
+        listen.album = listen.album.take().filter(|album| !album.trim().is_empty());
+    }
+    listens.sort_by_key(|listen| listen.timestamp);
+
+    Ok(listens)
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Listen>, ImportError> {
+    let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        return Ok(Vec::new());
+    };
+
+    let columns = csv_fields(header).map_err(|err| ImportError::Line(1, err))?;
+    let column = |name: &str| columns.iter().position(|column| column.trim().eq_ignore_ascii_case(name));
+    let (Some(artist), Some(track), Some(timestamp)) = (column("artist"), column("track"), column("timestamp")) else {
+        return Err(ImportError::Header(header.to_owned()));
+    };
+    let album = column("album");
+
+    lines
+        .map(|(index, line)| {
+            let line_number = index + 1;
+            let fields = csv_fields(line).map_err(|err| ImportError::Line(line_number, err))?;
+            let field = |column: usize| fields.get(column).map(|field| field.trim().to_owned());
+            let missing = |name: &str| ImportError::Line(line_number, format!("no {name}"));
+
+            let timestamp = field(timestamp).ok_or_else(|| missing("timestamp"))?;
+            Ok(Listen {
+                artist: field(artist).ok_or_else(|| missing("artist"))?,
+                track: field(track).ok_or_else(|| missing("track"))?,
+                album: album.and_then(field),
+                timestamp: timestamp
+                    .parse()
+                    .map_err(|err| ImportError::Line(line_number, format!("timestamp {timestamp:?} ({err})")))?,
+            })
+        })
+        .collect()
+}
+
+/// Splits a CSV line on its commas. Fields can be quoted to have commas in them, with quotes inside doubled up.
+fn csv_fields(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            _ => field.push(char),
+        }
+    }
+
+    if quoted {
+        return Err("a quote is never closed".to_owned());
+    }
+
+    fields.push(field);
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    fn listen(track: &str, album: Option<&str>, timestamp: u64) -> Listen {
+        Listen {
+            artist: "Catfish and the Bottlemen".to_owned(),
+            track: track.to_owned(),
+            album: album.map(str::to_owned),
+            timestamp,
+        }
+    }
+
+    #[test]
+    fn csv_listens() {
+        let csv = "timestamp,artist,track,album\n\
+                   1700000209,Catfish and the Bottlemen,Kathleen,\n\
+                   1700000000,Catfish and the Bottlemen,7,\"The Balcony\"\n\
+                   \n\
+                   1700000400,\"Catfish and the Bottlemen\",\"Hourglass, \"\"acoustic\"\"\",The Ride\n";
+        assert_eq!(
+            parse_csv(csv).unwrap(),
+            vec![
+                listen("Kathleen", Some(""), 1_700_000_209),
+                listen("7", Some("The Balcony"), 1_700_000_000),
+                listen("Hourglass, \"acoustic\"", Some("The Ride"), 1_700_000_400),
+            ]
+        );
+
+        assert!(matches!(parse_csv("artist,song,timestamp\n"), Err(ImportError::Header(_))));
+        assert!(matches!(
+            parse_csv("artist,track,timestamp\na,b,yesterday\n"),
+            Err(ImportError::Line(2, _))
+        ));
+        assert!(matches!(parse_csv("artist,track,timestamp\n\"a,b,1\n"), Err(ImportError::Line(2, _))));
+        assert!(parse_csv("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn reads_files() {
+        let dir = env::temp_dir().join(format!("ample-import-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let json = dir.join("listens.JSON");
+        fs::write(
+            &json,
+            r#"[{"artist":"Catfish and the Bottlemen","track":"Kathleen","album":"","timestamp":1700000209},
+                {"artist":"Catfish and the Bottlemen","track":"7","album":"The Balcony","timestamp":1700000000}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_listens(&json).unwrap(),
+            vec![listen("7", Some("The Balcony"), 1_700_000_000), listen("Kathleen", None, 1_700_000_209)]
+        );
+
+        let csv = dir.join("listens.csv");
+        fs::write(&csv, "artist,track,timestamp\nCatfish and the Bottlemen,7,1700000000\n").unwrap();
+        assert_eq!(read_listens(&csv).unwrap(), vec![listen("7", None, 1_700_000_000)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
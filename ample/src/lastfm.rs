@@ -1,28 +1,57 @@
-use keyring::Entry;
 use log::debug;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer, de};
+use sys_media::MediaInfo;
 use thiserror::Error;
-use ureq::Agent;
+use ureq::{Agent, Body, http::Response};
 
 use std::{
     collections::HashMap,
-    env,
-    time::{SystemTime, UNIX_EPOCH},
+    hash::{BuildHasher, Hasher, RandomState},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use crate::{secrets, uri};
+use crate::{
+    cache::LruCache,
+    import::Listen,
+    ratelimit::RateLimiter,
+    scrobbler::{self, Corrections, Scrobbler},
+    secrets,
+    uri::{self, QueryString},
+};
 const SESSION_ENTRY_NAME: &str = "ampleSession";
 
-const API_ROOT: &str = "https://ws.audioscrobbler.com/2.0";
+/// Root of the LastFM 2.0 API. Other services that speak the same protocol (like Libre.fm) have their own root.
+pub const DEFAULT_API_ROOT: &str = "https://ws.audioscrobbler.com/2.0";
+
+/// Error code LastFM answers with when the session key has expired or been revoked
+const INVALID_SESSION_KEY: i64 = 9;
+/// Error code for too many requests, which doesn't always come with a 429 status
+const RATE_LIMIT_EXCEEDED: i64 = 29;
+
+const MAX_REQUEST_ATTEMPTS: u32 = 4;
+const BASE_RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(8);
+/// Most listens LastFM takes in one track.scrobble request.
+pub const MAX_BATCH_SCROBBLES: usize = 50;
+const CREDS_BASE_RETRY_DELAY: Duration = Duration::from_millis(500);
+const CREDS_MAX_RETRY_DELAY: Duration = Duration::from_secs(16);
 
 #[derive(Debug, Clone)]
 pub struct LastFm {
     client: ureq::Agent,
     creds: LastFmCreds,
+    api_root: String,
+    // shared between clones so that every copy benefits from previous lookups
+    track_cache: Arc<Mutex<LruCache<(String, String), TrackInfo>>>,
+    // also shared, since the limit is for the whole account
+    rate_limiter: Arc<RateLimiter>,
 }
 
 #[derive(Debug, Clone)]
 pub struct LastFmCreds {
+    pub username: String,
     pub api_key: String,
     pub api_secret: String,
     pub session_token: String,
@@ -52,60 +81,324 @@ struct TrackInfoResponse {
 }
 
 #[derive(Deserialize, Debug)]
+struct AlbumInfoResponse {
+    pub album: AlbumInfo,
+}
+
+#[derive(Deserialize, Debug)]
+struct NowPlayingResponse {
+    nowplaying: ScrobbleResult,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScrobbleResponse {
+    scrobbles: Scrobbles,
+}
+
+#[derive(Deserialize, Debug)]
+struct Scrobbles {
+    // Only ever a single object since tracks are scrobbled one at a time
+    scrobble: ScrobbleResult,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchScrobbleResponse {
+    scrobbles: BatchScrobbles,
+}
+
+#[derive(Deserialize, Debug)]
+struct BatchScrobbles {
+    #[serde(rename = "@attr")]
+    counts: ScrobbleCounts,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScrobbleCounts {
+    #[serde(default, deserialize_with = "string_as_u64")]
+    accepted: Option<u64>,
+    #[serde(default, deserialize_with = "string_as_u64")]
+    ignored: Option<u64>,
+}
+
+/// The names LastFM ended up using for a scrobbled or now playing track.
+#[derive(Deserialize, Debug)]
+struct ScrobbleResult {
+    artist: CorrectableName,
+    track: CorrectableName,
+    album: Option<CorrectableName>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CorrectableName {
+    // "1" when LastFM replaced the name it was given
+    #[serde(default, deserialize_with = "string_as_u64")]
+    corrected: Option<u64>,
+    #[serde(rename = "#text", default)]
+    text: String,
+}
+
+impl CorrectableName {
+    fn correction(&self) -> Option<String> {
+        (self.corrected == Some(1) && !self.text.is_empty()).then(|| self.text.clone())
+    }
+}
+
+impl ScrobbleResult {
+    fn corrections(&self) -> Corrections {
+        Corrections {
+            artist: self.artist.correction(),
+            track: self.track.correction(),
+            album: self.album.as_ref().and_then(CorrectableName::correction),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct UserInfoResponse {
+    user: UserInfo,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct UserInfo {
+    pub name: String,
+    /// Total number of scrobbles
+    #[serde(default, deserialize_with = "string_as_u64")]
+    pub playcount: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentTracksResponse {
+    recenttracks: RecentTracks,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentTracks {
+    // LastFM sends a lone object instead of a list when there's only one track
+    #[serde(default, deserialize_with = "one_or_many")]
+    track: Vec<RecentTrackResult>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentTrackResult {
+    name: String,
+    artist: TextValue,
+    album: Option<TextValue>,
+    // Missing for the track that's playing right now
+    date: Option<ScrobbleDate>,
+    #[serde(rename = "@attr")]
+    attr: Option<RecentTrackAttr>,
+}
+
+#[derive(Deserialize, Debug)]
+struct TextValue {
+    #[serde(rename = "#text", default)]
+    text: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ScrobbleDate {
+    #[serde(default, deserialize_with = "string_as_u64")]
+    uts: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RecentTrackAttr {
+    #[serde(default)]
+    nowplaying: String,
+}
+
+/// A track from the user's listening history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecentTrack {
+    pub artist: String,
+    pub track: String,
+    pub album: String,
+    /// When the track was scrobbled. None for the track that's playing right now, which hasn't been scrobbled yet
+    pub timestamp: Option<SystemTime>,
+    pub now_playing: bool,
+}
+
+impl From<RecentTrackResult> for RecentTrack {
+    fn from(result: RecentTrackResult) -> Self {
+        RecentTrack {
+            artist: result.artist.text,
+            track: result.name,
+            album: result.album.map(|album| album.text).unwrap_or_default(),
+            timestamp: result.date.and_then(|date| date.uts).map(|uts| UNIX_EPOCH + Duration::from_secs(uts)),
+            now_playing: result.attr.is_some_and(|attr| attr.nowplaying == "true"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
 pub struct TrackInfo {
     pub name: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub mbid: Option<String>,
+    /// Length of the track in milliseconds
+    #[serde(rename = "duration", default, deserialize_with = "string_as_u64")]
+    pub duration_ms: Option<u64>,
+    /// How many times the track has been scrobbled by everyone
+    #[serde(default, deserialize_with = "string_as_u64")]
+    pub playcount: Option<u64>,
+    /// How many times the track has been scrobbled by the logged in user
+    #[serde(default, deserialize_with = "string_as_u64")]
+    pub userplaycount: Option<u64>,
     pub artist: ArtistInfo,
     pub album: Option<AlbumInfo>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ArtistInfo {
     pub name: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub mbid: Option<String>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct AlbumInfo {
     pub artist: String,
+    // track.getInfo calls it the title, album.getInfo the name
+    #[serde(alias = "name")]
     pub title: String,
+    #[serde(default, deserialize_with = "empty_as_none")]
+    pub mbid: Option<String>,
 
-    #[serde(rename = "image")]
+    #[serde(rename = "image", default, deserialize_with = "one_or_many")]
     pub images: Vec<ImageInfo>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct ImageInfo {
     // thought about making this an enum but I'm only gonna use
-    // small images
+    // small images. Empty when LastFM leaves out the size
+    #[serde(default)]
     pub size: String,
 
-    #[serde(rename = "#text")]
+    #[serde(rename = "#text", default)]
     pub url: String,
 }
 
+/// LastFM sends empty strings for values it doesn't have, like the MusicBrainz ids of lesser known tracks.
+fn empty_as_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Option::<String>::deserialize(deserializer)?;
+    Ok(value.filter(|value| !value.is_empty()))
+}
+
+/// LastFM sends numbers as strings, which might also be empty or missing.
+fn string_as_u64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Number {
+        Int(u64),
+        Str(String),
+    }
+
+    match Option::<Number>::deserialize(deserializer)? {
+        Some(Number::Int(value)) => Ok(Some(value)),
+        Some(Number::Str(value)) if value.trim().is_empty() => Ok(None),
+        Some(Number::Str(value)) => value.trim().parse().map(Some).map_err(de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+/// LastFM sends a single object instead of a list with one item in it.
+fn one_or_many<'de, D: Deserializer<'de>, T: Deserialize<'de>>(deserializer: D) -> Result<Vec<T>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        Many(Vec<T>),
+        One(T),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::Many(values) => Ok(values),
+        OneOrMany::One(value) => Ok(vec![value]),
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum CredsError {
-    // static lifetime of key since it should be a string literal
-    #[error("Error obtaining environment variable, {0}, because {1}")]
-    Env(&'static str, env::VarError),
     #[error("Error obtaining credentials from keyring: {0}")]
     Keyring(#[from] keyring::Error),
+    #[error("LastFM username has not been set! Check the README for more info about setting your LastFM username.")]
+    MissingUsername,
     #[error("LastFM password has not been set! Check the README for more info about setting your LastFM password.")]
     MissingPassword,
     #[error("LastFM secret has not been set! Check the README for more info about setting your LastFM API secret.")]
     MissingApiSecret,
+    #[error("LastFM API key has not been set! Check the README for more info about setting your LastFM API key.")]
+    MissingApiKey,
     #[error("Http error: {0}")]
     Http(#[from] ureq::Error),
+    /// A failure that's likely to go away on its own, like LastFM being down or the connection dropping.
+    /// The code is LastFM's error code, the HTTP status, or -1 if there was no response at all
     #[error("{1}")]
     RetryableError(i64, String),
+    /// LastFM answered, but won't log in with these credentials. Trying again won't help
+    #[error("LastFM rejected the login: {1} (error {0})")]
+    Rejected(i64, String),
+}
+
+impl CredsError {
+    /// Wraps an error from sending the request or reading the response, which is retryable if the connection was the problem.
+    fn from_http(err: ureq::Error) -> CredsError {
+        if is_retryable(&err) {
+            CredsError::RetryableError(-1, err.to_string())
+        } else {
+            CredsError::Http(err)
+        }
+    }
+
+    /// Works out why LastFM answered the login request with the error `status` and `body`.
+    fn from_response(status: u16, body: &str) -> CredsError {
+        match serde_json::from_str::<LastFmErrorResponse>(body) {
+            // Operation failed, service offline, temporarily unavailable and rate limit exceeded
+            Ok(err) if matches!(err.error, 8 | 11 | 16 | 29) => CredsError::RetryableError(err.error, err.message),
+            Ok(err) => CredsError::Rejected(err.error, err.message),
+            // Proxies and load balancers in front of LastFM don't answer with LastFM's errors
+            Err(_) if is_retryable(&ureq::Error::StatusCode(status)) => {
+                CredsError::RetryableError(status.into(), format!("LastFM answered with status {status}"))
+            }
+            Err(_) => CredsError::Http(ureq::Error::StatusCode(status)),
+        }
+    }
 }
 
 impl LastFm {
-    pub fn new(client: ureq::Agent, creds: LastFmCreds) -> LastFm {
-        LastFm { client, creds }
+    /// `api_root` is the root url of the API to talk to, usually [DEFAULT_API_ROOT].
+    /// `track_cache_size` is the max number of `get_track_info` results kept in memory.
+    /// `rate_limit` is the most requests per second to send, 0 for no limit.
+    pub fn new(client: ureq::Agent, creds: LastFmCreds, api_root: &str, track_cache_size: usize, rate_limit: f64) -> LastFm {
+        LastFm {
+            client,
+            creds,
+            api_root: normalize_api_root(api_root),
+            track_cache: Arc::new(Mutex::new(LruCache::new(track_cache_size))),
+            rate_limiter: Arc::new(RateLimiter::new(rate_limit)),
+        }
+    }
+
+    /// Sends a request with [with_retry], waiting for the rate limiter before every attempt.
+    fn send_request<T>(&self, mut request: impl FnMut() -> Result<T, ureq::Error>) -> Result<T, ureq::Error> {
+        with_retry(|| {
+            self.rate_limiter.acquire();
+            request()
+        })
     }
 
-    pub fn scrobble(&self, artist: &str, track: &str, timestamp: SystemTime, album: Option<&str>) -> Result<(), ureq::Error> {
-        let timestamp_str = format!("{}", timestamp.duration_since(UNIX_EPOCH).unwrap().as_secs());
+    /// `duration` is the length of the track in seconds, which LastFM uses to help match the track.
+    /// Returns the names LastFM corrected, if any.
+    pub fn scrobble(
+        &self,
+        artist: &str,
+        track: &str,
+        timestamp: SystemTime,
+        album: Option<&str>,
+        duration: Option<u64>,
+    ) -> Result<Corrections, ureq::Error> {
+        let timestamp_str = scrobbler::unix_timestamp(timestamp).to_string();
+        let duration_str = duration.map(|duration| duration.to_string());
         let mut params = HashMap::new();
         params.insert("method", "track.scrobble");
         params.insert("artist", artist);
@@ -118,25 +411,61 @@ impl LastFm {
             params.insert("album", album);
         }
 
+        if let Some(ref duration) = duration_str {
+            params.insert("duration", duration);
+        }
+
         let sig = create_api_sig(&params, &self.creds.api_secret);
         params.insert("format", "json");
         params.insert("api_sig", &sig);
 
-        let mut rep = self.client.post(API_ROOT).send_form(params)?;
-        let body = rep.body_mut().read_to_string()?;
+        let body = self.send_request(|| read_body(self.client.post(&self.api_root).send_form(&params)?))?;
 
-        // ureq::http_status_as_error is set to false so that this can happen
-        // inbetween the error. There might be a better way of doing this but im not sure.
-        debug!("{body}");
+        // The scrobble went through either way, so a response that can't be read just means no corrections
+        match serde_json::from_str::<ScrobbleResponse>(&body) {
+            Ok(rep) => Ok(rep.scrobbles.scrobble.corrections()),
+            Err(err) => {
+                debug!("Failed to read scrobble response: {err}");
+                Ok(Corrections::default())
+            }
+        }
+    }
 
-        if rep.status().is_client_error() || rep.status().is_server_error() {
-            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+    /// Scrobbles up to [MAX_BATCH_SCROBBLES] listens in a single request, for listens that weren't scrobbled while they played.
+    /// Returns how many LastFM accepted and how many it ignored, e.g. for being more than two weeks old.
+    pub fn scrobble_batch(&self, listens: &[Listen]) -> Result<(u64, u64), ureq::Error> {
+        let mut numbered = Vec::new();
+        for (i, listen) in listens.iter().take(MAX_BATCH_SCROBBLES).enumerate() {
+            numbered.push((format!("artist[{i}]"), listen.artist.clone()));
+            numbered.push((format!("track[{i}]"), listen.track.clone()));
+            numbered.push((format!("timestamp[{i}]"), listen.timestamp.to_string()));
+            if let Some(ref album) = listen.album {
+                numbered.push((format!("album[{i}]"), album.clone()));
+            }
         }
 
-        Ok(())
+        let mut params: HashMap<&str, &str> = numbered.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        params.insert("method", "track.scrobble");
+        params.insert("api_key", &self.creds.api_key);
+        params.insert("sk", &self.creds.session_token);
+
+        let sig = create_api_sig(&params, &self.creds.api_secret);
+        params.insert("format", "json");
+        params.insert("api_sig", &sig);
+
+        let body = self.send_request(|| read_body(self.client.post(&self.api_root).send_form(&params)?))?;
+
+        let rep: BatchScrobbleResponse = serde_json::from_str(&body)?;
+        Ok((
+            rep.scrobbles.counts.accepted.unwrap_or_default(),
+            rep.scrobbles.counts.ignored.unwrap_or_default(),
+        ))
     }
 
-    pub fn now_playing(&self, artist: &str, track: &str, album: Option<&str>) -> Result<(), ureq::Error> {
+    /// `duration` is the length of the track in seconds, which LastFM uses to know when the track has stopped playing.
+    /// Returns the names LastFM corrected, if any.
+    pub fn now_playing(&self, artist: &str, track: &str, album: Option<&str>, duration: Option<u64>) -> Result<Corrections, ureq::Error> {
+        let duration_str = duration.map(|duration| duration.to_string());
         let mut params = HashMap::new();
         params.insert("method", "track.updateNowPlaying");
         params.insert("artist", artist);
@@ -148,60 +477,185 @@ impl LastFm {
             params.insert("album", album);
         }
 
+        if let Some(ref duration) = duration_str {
+            params.insert("duration", duration);
+        }
+
         let sig = create_api_sig(&params, &self.creds.api_secret);
         params.insert("format", "json");
         params.insert("api_sig", &sig);
 
-        let mut rep = self.client.post(API_ROOT).send_form(params)?;
-        let body = rep.body_mut().read_to_string()?;
-
-        debug!("{body}");
+        let body = self.send_request(|| read_body(self.client.post(&self.api_root).send_form(&params)?))?;
 
-        if rep.status().is_client_error() || rep.status().is_server_error() {
-            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+        match serde_json::from_str::<NowPlayingResponse>(&body) {
+            Ok(rep) => Ok(rep.nowplaying.corrections()),
+            Err(err) => {
+                debug!("Failed to read now playing response: {err}");
+                Ok(Corrections::default())
+            }
         }
-
-        Ok(())
     }
 
+    /// Gets info about a track from LastFM. Results are cached, so repeated
+    /// lookups of the same artist and track won't hit the API again.
     pub fn get_track_info(&self, artist: &str, track: &str) -> Result<TrackInfo, ureq::Error> {
+        let cache_key = (artist.to_owned(), track.to_owned());
+        if let Some(cached) = self.track_cache.lock().unwrap().get(&cache_key) {
+            debug!("Using cached track info for {track} by {artist}");
+            return Ok(cached);
+        }
+
         let mut params = HashMap::new();
         params.insert("method", "track.getInfo");
         params.insert("artist", artist);
         params.insert("track", track);
+        // Makes LastFM include the user's own play count
+        params.insert("username", &self.creds.username);
         params.insert("api_key", &self.creds.api_key);
         params.insert("format", "json");
 
-        let uri = create_param_uri(&params, None);
-        debug!("{uri}");
-        let mut rep = self.client.get(uri).call()?;
-        let body = rep.body_mut().read_to_string()?;
+        let uri = create_param_uri(&self.api_root, &params, None);
+        debug!("{}", uri::percent_decode(&uri).unwrap_or_else(|_| uri.clone()));
+        let body = self.send_request(|| read_body(self.client.get(&uri).call()?))?;
 
-        debug!("{body}");
+        let track: TrackInfoResponse = serde_json::from_str(&body)?;
+        self.track_cache.lock().unwrap().insert(cache_key, track.track.clone());
 
-        if rep.status().is_client_error() || rep.status().is_server_error() {
-            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+        Ok(track.track)
+    }
+
+    /// Gets info about an album from LastFM, which often has art when none of its tracks do.
+    /// The names are the ones LastFM knows the album by, which might be corrected from the ones given.
+    pub fn get_album_info(&self, artist: &str, album: &str) -> Result<AlbumInfo, ureq::Error> {
+        let mut params = HashMap::new();
+        params.insert("method", "album.getInfo");
+        params.insert("artist", artist);
+        params.insert("album", album);
+        params.insert("autocorrect", "1");
+        params.insert("api_key", &self.creds.api_key);
+        params.insert("format", "json");
+
+        let uri = create_param_uri(&self.api_root, &params, None);
+        debug!("{}", uri::percent_decode(&uri).unwrap_or_else(|_| uri.clone()));
+        let body = self.send_request(|| read_body(self.client.get(&uri).call()?))?;
+
+        let album: AlbumInfoResponse = serde_json::from_str(&body)?;
+        Ok(album.album)
+    }
+
+    /// Gets the logged in user's profile. Cheap enough to check that the username and API key work.
+    pub fn get_user_info(&self) -> Result<UserInfo, ureq::Error> {
+        let mut params = HashMap::new();
+        params.insert("method", "user.getInfo");
+        params.insert("user", &self.creds.username);
+        params.insert("api_key", &self.creds.api_key);
+        params.insert("format", "json");
+
+        let uri = create_param_uri(&self.api_root, &params, None);
+        debug!("{}", uri::percent_decode(&uri).unwrap_or_else(|_| uri.clone()));
+        let body = self.send_request(|| read_body(self.client.get(&uri).call()?))?;
+
+        let user: UserInfoResponse = serde_json::from_str(&body)?;
+        Ok(user.user)
+    }
+
+    /// Checks that LastFM still accepts the stored session key by asking who it belongs to.
+    /// Returns false if the session has expired or been revoked, in which case every scrobble will fail until ample logs in again.
+    pub fn validate_session(&self) -> Result<bool, ureq::Error> {
+        let mut params = HashMap::new();
+        params.insert("method", "user.getInfo");
+        params.insert("api_key", &self.creds.api_key);
+        params.insert("sk", &self.creds.session_token);
+
+        let sig = create_api_sig(&params, &self.creds.api_secret);
+        let uri = create_param_uri(&self.api_root, &params, Some(sig));
+
+        // Like read_body, but the body of a client error is kept to see why LastFM rejected the session
+        let (status, body) = self.send_request(|| {
+            let mut rep = self.client.get(&uri).call()?;
+            let body = rep.body_mut().read_to_string()?;
+            debug!("{body}");
+
+            match error_status(rep.status().as_u16(), &body) {
+                status @ (429 | 500..600) => Err(ureq::Error::StatusCode(status)),
+                status => Ok((status, body)),
+            }
+        })?;
+
+        if status < 400 {
+            Ok(true)
+        } else if is_invalid_session(&body) {
+            Ok(false)
+        } else {
+            Err(ureq::Error::StatusCode(status))
         }
+    }
 
-        let track: TrackInfoResponse = serde_json::from_str(&body)?;
+    /// Gets the user's last `limit` scrobbles, newest first.
+    /// If something is playing right now, it comes first with `now_playing` set.
+    pub fn get_recent_tracks(&self, limit: u32) -> Result<Vec<RecentTrack>, ureq::Error> {
+        let limit_str = limit.to_string();
+        let mut params = HashMap::new();
+        params.insert("method", "user.getRecentTracks");
+        params.insert("user", &self.creds.username);
+        params.insert("limit", &limit_str);
+        params.insert("api_key", &self.creds.api_key);
+        params.insert("format", "json");
 
-        Ok(track.track)
+        let uri = create_param_uri(&self.api_root, &params, None);
+        debug!("{}", uri::percent_decode(&uri).unwrap_or_else(|_| uri.clone()));
+        let body = self.send_request(|| read_body(self.client.get(&uri).call()?))?;
+
+        let recent: RecentTracksResponse = serde_json::from_str(&body)?;
+        Ok(recent.recenttracks.track.into_iter().map(RecentTrack::from).collect())
     }
 }
 
+impl Scrobbler for LastFm {
+    fn name(&self) -> &'static str {
+        "LastFM"
+    }
+
+    fn now_playing(&self, info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+        self.now_playing(&info.artist_name, &info.song_name, Some(&info.album_name), duration_secs(info))
+    }
+
+    fn scrobble(&self, info: &MediaInfo, timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+        self.scrobble(&info.artist_name, &info.song_name, timestamp, Some(&info.album_name), duration_secs(info))
+    }
+
+    fn track_info(&self, info: &MediaInfo) -> Result<Option<TrackInfo>, ureq::Error> {
+        self.get_track_info(&info.artist_name, &info.song_name).map(Some)
+    }
+
+    fn album_info(&self, info: &MediaInfo) -> Result<Option<AlbumInfo>, ureq::Error> {
+        if info.album_name.is_empty() {
+            return Ok(None);
+        }
+        self.get_album_info(&info.artist_name, &info.album_name).map(Some)
+    }
+}
+
+/// Length of the track in whole seconds, or None if the player didn't report one.
+fn duration_secs(info: &MediaInfo) -> Option<u64> {
+    let secs = info.length().unwrap_or_default().as_secs();
+    if secs == 0 { None } else { Some(secs) }
+}
+
 /// Represents all required credentials for autheticated LastFM API requests.
 /// This struct uses mobile authentication so that the application does not have to
 /// open a web browser.
 impl LastFmCreds {
-    pub fn get_creds(client: Agent) -> Result<LastFmCreds, CredsError> {
-        let api_key = env::var("AMPLE_FM_API_KEY").map_err(|var_error| CredsError::Env("AMPLE_FM_API_KEY", var_error))?;
-        let username = env::var("AMPLE_FM_USERNAME").map_err(|var_error| CredsError::Env("AMPLE_FM_USERNAME", var_error))?;
+    pub fn get_creds(client: Agent, api_root: &str) -> Result<LastFmCreds, CredsError> {
+        let api_root = normalize_api_root(api_root);
+        let api_key = secrets::get_lastfm_api_key().ok_or(CredsError::MissingApiKey)?;
+        let username = secrets::get_lastfm_username().ok_or(CredsError::MissingUsername)?;
 
         let password = secrets::get_lastfm_password().ok_or(CredsError::MissingPassword)?;
         let secret = secrets::get_lastfm_secret().ok_or(CredsError::MissingApiSecret)?;
 
-        let session_entry = Entry::new_with_target(SESSION_ENTRY_NAME, crate::APP_NAME, crate::APP_NAME)?;
-        let session_token = match session_entry.get_password() {
+        let session_entry = session_entry_name(&api_root);
+        let session_token = match secrets::get_entry(&session_entry) {
             Err(err) => {
                 // Ask LastFM for session token
                 if let keyring::Error::NoEntry = err {
@@ -216,27 +670,21 @@ impl LastFmCreds {
                     map_params.insert("format", "json");
 
                     debug!("sig: {sig}");
-                    debug!("uri: {API_ROOT}");
+                    debug!("uri: {api_root}");
 
-                    let mut rep = client.post(API_ROOT).send_form(map_params)?;
+                    let mut rep = client.post(&api_root).send_form(map_params).map_err(CredsError::from_http)?;
 
-                    let body = rep.body_mut().read_to_string()?;
+                    let body = rep.body_mut().read_to_string().map_err(CredsError::from_http)?;
 
                     debug!("{body}");
                     if rep.status().is_client_error() || rep.status().is_server_error() {
-                        return match serde_json::from_str::<LastFmErrorResponse>(&body) {
-                            Ok(err) => match err.error {
-                                8 | 11 | 16 | 29 => Err(CredsError::RetryableError(err.error, err.message)),
-                                _ => Err(CredsError::Http(ureq::Error::StatusCode(rep.status().as_u16()))),
-                            },
-                            Err(_) => Err(CredsError::Http(ureq::Error::StatusCode(rep.status().as_u16()))),
-                        };
+                        return Err(CredsError::from_response(rep.status().as_u16(), &body));
                     }
 
                     let json_response: AuthMobileSessionResponse = serde_json::from_str(&body).map_err(ureq::Error::Json)?;
                     let key = json_response.session.key;
 
-                    session_entry.set_password(&key)?;
+                    secrets::set_entry(&session_entry, &key)?;
 
                     key
                 } else {
@@ -247,6 +695,7 @@ impl LastFmCreds {
         };
 
         Ok(LastFmCreds {
+            username,
             api_key,
             api_secret: secret,
             session_token,
@@ -254,43 +703,145 @@ impl LastFmCreds {
     }
 }
 
+/// Reads the body of a response, turning error statuses into errors.
+///
+/// ureq::http_status_as_error is set to false so that the body can be logged
+/// inbetween the error. There might be a better way of doing this but im not sure.
+fn read_body(mut rep: Response<Body>) -> Result<String, ureq::Error> {
+    let body = rep.body_mut().read_to_string()?;
+
+    debug!("{body}");
+
+    if rep.status().is_client_error() || rep.status().is_server_error() {
+        return Err(ureq::Error::StatusCode(error_status(rep.status().as_u16(), &body)));
+    }
+
+    Ok(body)
+}
+
+/// The status of an error response, except that LastFM's rate limit error is always a 429 so that it gets retried after a wait.
+fn error_status(status: u16, body: &str) -> u16 {
+    match serde_json::from_str::<LastFmErrorResponse>(body) {
+        Ok(err) if err.error == RATE_LIMIT_EXCEEDED => 429,
+        _ => status,
+    }
+}
+
+/// Whether the body of an error response says the session key isn't valid anymore.
+fn is_invalid_session(body: &str) -> bool {
+    serde_json::from_str::<LastFmErrorResponse>(body).is_ok_and(|err| err.error == INVALID_SESSION_KEY)
+}
+
+/// Returns true for errors that are likely to be gone if the request is retried:
+/// server errors, rate limiting (429), and connection problems.
+/// Client errors like a bad session or API key will fail the same way every time.
+pub fn is_retryable(err: &ureq::Error) -> bool {
+    match err {
+        ureq::Error::StatusCode(code) => *code == 429 || (500..600).contains(code),
+        ureq::Error::Io(_) | ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed | ureq::Error::HostNotFound => true,
+        _ => false,
+    }
+}
+
+/// Calls `request` until it succeeds, fails with a non-retryable error, or runs out of attempts.
+/// The wait between attempts doubles each time (1s, 2s, 4s, ...) up to MAX_RETRY_DELAY, which also
+/// gives LastFM's rate limiter time to calm down when it answers with a 429.
+fn with_retry<T>(mut request: impl FnMut() -> Result<T, ureq::Error>) -> Result<T, ureq::Error> {
+    let mut delay = BASE_RETRY_DELAY;
+    let mut attempt = 1;
+
+    loop {
+        match request() {
+            Err(err) if attempt < MAX_REQUEST_ATTEMPTS && is_retryable(&err) => {
+                debug!("LastFM request failed on attempt {attempt}: {err}. Retrying in {delay:?}");
+                thread::sleep(delay);
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// How long to wait after failed login attempt number `attempt` (starting at 1) before trying again.
+/// The delay doubles from 500ms up to 16s and gets up to half of itself added at random, so that
+/// clients which all lost their connection at once don't all retry at the same moment.
+pub fn creds_retry_delay(attempt: u32) -> Duration {
+    // RandomState is seeded randomly, which is plenty for jitter without pulling in a rand crate
+    let random = RandomState::new().build_hasher().finish();
+    backoff_delay(attempt, random as f64 / u64::MAX as f64)
+}
+
+/// The delay for `attempt` with `jitter` (between 0 and 1) of half the delay added.
+fn backoff_delay(attempt: u32, jitter: f64) -> Duration {
+    let delay = CREDS_BASE_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(CREDS_MAX_RETRY_DELAY);
+    delay + delay.mul_f64(jitter.clamp(0.0, 1.0) / 2.0)
+}
+
 /// Creates an MD5 hash needed to sign API requests.
 fn create_api_sig(params: &HashMap<&str, &str>, secret: &str) -> String {
+    let unhashed_api_string = api_sig_base(params, secret);
+
+    debug!("Unhashed API sig: {unhashed_api_string}");
+
+    let dig = md5::compute(unhashed_api_string);
+
+    format!("{dig:x}")
+}
+
+/// Params LastFM leaves out when checking signatures, so they can be in the map whenever it's signed.
+const UNSIGNED_PARAMS: [&str; 3] = ["format", "callback", "api_sig"];
+
+/// The string that gets hashed into the signature: every name and value, sorted by name, followed by the secret.
+fn api_sig_base(params: &HashMap<&str, &str>, secret: &str) -> String {
     let mut unhashed_api_string = String::new();
-    let mut sorted_params: Vec<(&&str, &&str)> = params.iter().collect();
-    sorted_params.sort_by(|a, b| a.0.cmp(b.0));
 
-    for (name, value) in sorted_params {
+    // use the same ordering as the query string so the two can't diverge
+    for (name, value) in query_string(params).sorted_params() {
+        if UNSIGNED_PARAMS.contains(&name) {
+            continue;
+        }
+
         unhashed_api_string.push_str(name);
         unhashed_api_string.push_str(value);
     }
 
     unhashed_api_string.push_str(secret);
+    unhashed_api_string
+}
 
-    debug!("Unhashed API sig: {unhashed_api_string}");
+fn query_string<'a>(params: &HashMap<&'a str, &'a str>) -> QueryString<'a> {
+    params.iter().fold(QueryString::new(), |query, (&name, &value)| query.param(name, value))
+}
 
-    let dig = md5::compute(unhashed_api_string);
+/// Removes the stored session token for the given api root so that a new one is requested next time.
+/// Returns false if there was no session stored.
+pub fn forget_session(api_root: &str) -> Result<bool, keyring::Error> {
+    secrets::forget_entry(&session_entry_name(&normalize_api_root(api_root)))
+}
 
-    format!("{dig:x}")
+/// Strips trailing slashes so that the root can be joined with paths the same way for every service.
+fn normalize_api_root(api_root: &str) -> String {
+    api_root.trim_end_matches('/').to_owned()
 }
 
-/// Creates a uri from API_ROOT that contains the given params.
+/// Session tokens are only valid for the service that issued them, so sessions for
+/// anything other than LastFM itself are kept in their own entry.
+fn session_entry_name(api_root: &str) -> String {
+    if api_root == DEFAULT_API_ROOT {
+        SESSION_ENTRY_NAME.to_owned()
+    } else {
+        format!("{SESSION_ENTRY_NAME}-{api_root}")
+    }
+}
+
+/// Creates a uri from the api root that contains the given params.
 /// For a more consistent output (since iterating through a HashMap has a random order),
 /// the parameters are sorted.
-fn create_param_uri(params: &HashMap<&str, &str>, sig: Option<String>) -> String {
-    let mut uri = format!("{API_ROOT}/?");
-    let mut params: Vec<(&&str, &&str)> = params.iter().collect();
-    params.sort_by(|a, b| a.0.cmp(b.0));
-    for (i, (name, value)) in params.into_iter().enumerate() {
-        if i != 0 {
-            uri.push('&');
-        }
-
-        let encoded_name = uri::percent_encode(name);
-        let encoded_value = uri::percent_encode(value);
-
-        uri.push_str(&format!("{encoded_name}={encoded_value}"));
-    }
+fn create_param_uri(api_root: &str, params: &HashMap<&str, &str>, sig: Option<String>) -> String {
+    let mut uri = format!("{api_root}/?{}", query_string(params).build());
 
     if let Some(sig) = sig {
         uri.push_str(&format!("&api_sig={sig}"));
@@ -313,10 +864,304 @@ mod tests {
         params.insert("api_key", "apple");
         params.insert("fortnite", "battlePass");
 
-        let uri = create_param_uri(&params, None);
+        let uri = create_param_uri(DEFAULT_API_ROOT, &params, None);
         assert_eq!(
             uri,
             "https://ws.audioscrobbler.com/2.0/?api_key=apple&fortnite=battlePass&method=juice&format=json"
         )
     }
+
+    #[test]
+    fn libre_fm_param_uri() {
+        let mut params = HashMap::new();
+        params.insert("method", "juice");
+
+        let uri = create_param_uri(&normalize_api_root("https://libre.fm/2.0/"), &params, None);
+        assert_eq!(uri, "https://libre.fm/2.0/?method=juice&format=json")
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(is_retryable(&ureq::Error::StatusCode(500)));
+        assert!(is_retryable(&ureq::Error::StatusCode(503)));
+        assert!(is_retryable(&ureq::Error::StatusCode(429)));
+        assert!(is_retryable(&ureq::Error::ConnectionFailed));
+
+        assert!(!is_retryable(&ureq::Error::StatusCode(400)));
+        assert!(!is_retryable(&ureq::Error::StatusCode(403)));
+    }
+
+    #[test]
+    fn invalid_session() {
+        assert!(is_invalid_session(
+            r#"{"message":"Invalid session key - Please re-authenticate","error":9}"#
+        ));
+        assert!(!is_invalid_session(
+            r#"{"message":"Invalid API key - You must be granted a valid key by last.fm","error":10}"#
+        ));
+        assert!(!is_invalid_session("<html>Bad Gateway</html>"));
+    }
+
+    #[test]
+    fn creds_backoff() {
+        assert_eq!(backoff_delay(1, 0.0), Duration::from_millis(500));
+        assert_eq!(backoff_delay(2, 0.0), Duration::from_secs(1));
+        assert_eq!(backoff_delay(4, 1.0), Duration::from_secs(6));
+        assert_eq!(backoff_delay(6, 0.0), Duration::from_secs(16));
+        assert_eq!(backoff_delay(40, 0.5), Duration::from_secs(20));
+
+        let delay = creds_retry_delay(3);
+        assert!(delay >= Duration::from_secs(2) && delay <= Duration::from_secs(3));
+    }
+
+    #[test]
+    fn creds_errors() {
+        let retryable = CredsError::from_response(503, r#"{"message":"Service Offline","error":11}"#);
+        assert!(matches!(retryable, CredsError::RetryableError(11, _)));
+        let rejected = CredsError::from_response(403, r#"{"message":"Authentication Failed","error":4}"#);
+        assert!(matches!(rejected, CredsError::Rejected(4, _)));
+
+        // Errors that don't come from LastFM itself
+        assert!(matches!(
+            CredsError::from_response(502, "<html>Bad Gateway</html>"),
+            CredsError::RetryableError(502, _)
+        ));
+        assert!(matches!(
+            CredsError::from_response(404, ""),
+            CredsError::Http(ureq::Error::StatusCode(404))
+        ));
+        assert!(matches!(
+            CredsError::from_http(ureq::Error::ConnectionFailed),
+            CredsError::RetryableError(-1, _)
+        ));
+    }
+
+    #[test]
+    fn rate_limit_status() {
+        assert_eq!(error_status(400, r#"{"message":"Rate Limit Exceeded","error":29}"#), 429);
+        assert_eq!(error_status(403, r#"{"message":"Invalid session key","error":9}"#), 403);
+        assert_eq!(error_status(502, ""), 502);
+        assert!(is_retryable(&ureq::Error::StatusCode(error_status(400, r#"{"error":29,"message":""}"#))));
+    }
+
+    #[test]
+    fn api_sig() {
+        let mut params = HashMap::new();
+        params.insert("username", "rj");
+        params.insert("method", "auth.getMobileSession");
+        params.insert("password", "hunter2");
+        params.insert("api_key", "apple");
+
+        assert_eq!(
+            api_sig_base(&params, "secret"),
+            "api_keyapplemethodauth.getMobileSessionpasswordhunter2usernamerjsecret"
+        );
+        assert_eq!(create_api_sig(&params, "secret"), "7464865007602fff5d5988f2a47f240e");
+    }
+
+    #[test]
+    fn non_ascii_api_sig() {
+        let mut params = HashMap::new();
+        params.insert("track", "Hoppípolla");
+        params.insert("artist", "Sigur Rós");
+        params.insert("method", "track.scrobble");
+        params.insert("api_key", "€");
+
+        // Values are signed as their UTF-8 bytes, not percent-encoded like in the query string
+        assert_eq!(
+            api_sig_base(&params, "secret"),
+            "api_key€artistSigur Rósmethodtrack.scrobbletrackHoppípollasecret"
+        );
+        assert_eq!(create_api_sig(&params, "secret"), "5424c4cd2d0bbc75c046bf86625350a3");
+    }
+
+    #[test]
+    fn unsigned_params() {
+        let mut params = HashMap::new();
+        params.insert("method", "auth.getMobileSession");
+        let unsigned = create_api_sig(&params, "secret");
+
+        params.insert("format", "json");
+        params.insert("callback", "handle");
+        params.insert("api_sig", "0123456789abcdef");
+
+        assert_eq!(api_sig_base(&params, "secret"), "methodauth.getMobileSessionsecret");
+        assert_eq!(create_api_sig(&params, "secret"), unsigned);
+    }
+
+    #[test]
+    fn track_info_mbids() {
+        let track: TrackInfo = serde_json::from_str(
+            r##"{
+                "name": "7",
+                "mbid": "",
+                "artist": { "name": "Catfish and the Bottlemen", "mbid": "e8d28b5e-9d8a-4a8c-9e1f-1c9d2a1b3c4d" },
+                "album": { "artist": "Catfish and the Bottlemen", "title": "The Balcony", "image": [{ "size": "large", "#text": "" }] }
+            }"##,
+        )
+        .unwrap();
+
+        assert_eq!(track.mbid, None);
+        assert_eq!(track.artist.mbid.as_deref(), Some("e8d28b5e-9d8a-4a8c-9e1f-1c9d2a1b3c4d"));
+        assert_eq!(track.album.unwrap().mbid, None);
+    }
+
+    #[test]
+    fn track_info_without_album() {
+        // Singles and lesser known releases often come without the album, or with an album that has no images
+        let track: TrackInfo = serde_json::from_str(r#"{ "name": "7", "artist": { "name": "Catfish and the Bottlemen" } }"#).unwrap();
+        assert!(track.album.is_none());
+
+        let track: TrackInfo = serde_json::from_str(
+            r#"{ "name": "7", "artist": { "name": "Catfish and the Bottlemen" }, "album": { "artist": "Catfish and the Bottlemen", "title": "7" } }"#,
+        )
+        .unwrap();
+        assert!(track.album.unwrap().images.is_empty());
+    }
+
+    #[test]
+    fn album_info() {
+        let album: AlbumInfoResponse = serde_json::from_str(
+            r##"{ "album": {
+                "artist": "Catfish and the Bottlemen",
+                "name": "The Balcony",
+                "mbid": "0d2e4b1a-3c5f-4e6a-8b7c-9d0e1f2a3b4c",
+                "url": "https://www.last.fm/music/Catfish+and+the+Bottlemen/The+Balcony",
+                "image": [
+                    { "size": "small", "#text": "https://lastfm.freetls.fastly.net/i/u/34s/balcony.png" },
+                    { "size": "large", "#text": "https://lastfm.freetls.fastly.net/i/u/174s/balcony.png" }
+                ],
+                "listeners": "512345"
+            } }"##,
+        )
+        .unwrap();
+
+        assert_eq!(album.album.title, "The Balcony");
+        assert_eq!(album.album.mbid.as_deref(), Some("0d2e4b1a-3c5f-4e6a-8b7c-9d0e1f2a3b4c"));
+        assert_eq!(album.album.images[1].url, "https://lastfm.freetls.fastly.net/i/u/174s/balcony.png");
+    }
+
+    #[test]
+    fn image_shapes() {
+        let album: AlbumInfo = serde_json::from_str(
+            r##"{ "artist": "Catfish and the Bottlemen", "title": "The Balcony", "image": { "size": "large", "#text": "https://lastfm.freetls.fastly.net/i/u/174s/balcony.png" } }"##,
+        )
+        .unwrap();
+        assert_eq!(album.images.len(), 1);
+        assert_eq!(album.images[0].size, "large");
+
+        let album: AlbumInfo = serde_json::from_str(
+            r##"{ "artist": "Catfish and the Bottlemen", "title": "The Balcony", "image": [{ "#text": "https://lastfm.freetls.fastly.net/i/u/balcony.png" }, { "size": "" }] }"##,
+        )
+        .unwrap();
+        assert_eq!(album.images[0].size, "");
+        assert_eq!(album.images[0].url, "https://lastfm.freetls.fastly.net/i/u/balcony.png");
+        assert_eq!(album.images[1].url, "");
+    }
+
+    #[test]
+    fn recent_tracks() {
+        let recent: RecentTracksResponse = serde_json::from_str(
+            r##"{ "recenttracks": { "track": [
+                {
+                    "name": "7",
+                    "artist": { "mbid": "", "#text": "Catfish and the Bottlemen" },
+                    "album": { "mbid": "", "#text": "The Balcony" },
+                    "@attr": { "nowplaying": "true" }
+                },
+                {
+                    "name": "Hey Jude",
+                    "artist": { "mbid": "", "#text": "The Beatles" },
+                    "album": { "mbid": "", "#text": "" },
+                    "date": { "uts": "1700000000", "#text": "14 Nov 2023, 22:13" }
+                }
+            ], "@attr": { "user": "rj", "page": "1" } } }"##,
+        )
+        .unwrap();
+        let tracks: Vec<RecentTrack> = recent.recenttracks.track.into_iter().map(RecentTrack::from).collect();
+
+        assert_eq!(
+            tracks,
+            vec![
+                RecentTrack {
+                    artist: "Catfish and the Bottlemen".to_owned(),
+                    track: "7".to_owned(),
+                    album: "The Balcony".to_owned(),
+                    timestamp: None,
+                    now_playing: true,
+                },
+                RecentTrack {
+                    artist: "The Beatles".to_owned(),
+                    track: "Hey Jude".to_owned(),
+                    album: String::new(),
+                    timestamp: Some(UNIX_EPOCH + Duration::from_secs(1700000000)),
+                    now_playing: false,
+                },
+            ]
+        );
+
+        let single: RecentTracksResponse =
+            serde_json::from_str(r##"{ "recenttracks": { "track": { "name": "7", "artist": { "#text": "Catfish" } } } }"##).unwrap();
+        assert_eq!(single.recenttracks.track.len(), 1);
+    }
+
+    #[test]
+    fn track_info_counts() {
+        let track: TrackInfo = serde_json::from_str(
+            r#"{
+                "name": "7",
+                "duration": "209000",
+                "playcount": "1337",
+                "userplaycount": "42",
+                "artist": { "name": "Catfish and the Bottlemen" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(track.duration_ms, Some(209000));
+        assert_eq!(track.playcount, Some(1337));
+        assert_eq!(track.userplaycount, Some(42));
+
+        let track: TrackInfo = serde_json::from_str(r#"{ "name": "7", "duration": "", "artist": { "name": "Catfish" } }"#).unwrap();
+        assert_eq!(track.duration_ms, None);
+        assert_eq!(track.userplaycount, None);
+    }
+
+    #[test]
+    fn scrobble_corrections() {
+        let rep: ScrobbleResponse = serde_json::from_str(
+            r##"{
+                "scrobbles": {
+                    "scrobble": {
+                        "artist": { "corrected": "1", "#text": "The Beatles" },
+                        "album": { "corrected": "0", "#text": "abbey road" },
+                        "track": { "corrected": "0", "#text": "Come Together" },
+                        "ignoredMessage": { "code": "0", "#text": "" },
+                        "timestamp": "1700000000"
+                    },
+                    "@attr": { "ignored": 0, "accepted": 1 }
+                }
+            }"##,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rep.scrobbles.scrobble.corrections(),
+            Corrections {
+                artist: Some("The Beatles".to_owned()),
+                track: None,
+                album: None,
+            }
+        );
+
+        let rep: BatchScrobbleResponse =
+            serde_json::from_str(r##"{ "scrobbles": { "scrobble": [], "@attr": { "ignored": "1", "accepted": 49 } } }"##).unwrap();
+        assert_eq!((rep.scrobbles.counts.accepted, rep.scrobbles.counts.ignored), (Some(49), Some(1)));
+
+        let rep: NowPlayingResponse = serde_json::from_str(
+            r##"{ "nowplaying": { "artist": { "corrected": "0", "#text": "Catfish and the Bottlemen" }, "track": { "corrected": "0", "#text": "7" } } }"##,
+        )
+        .unwrap();
+        assert!(rep.nowplaying.corrections().is_empty());
+    }
 }
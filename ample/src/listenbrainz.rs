@@ -0,0 +1,208 @@
+use log::debug;
+use serde::Serialize;
+use sys_media::MediaInfo;
+use ureq::Agent;
+
+use std::time::SystemTime;
+
+use crate::{
+    import,
+    scrobbler::{Corrections, Scrobbler, unix_timestamp},
+};
+
+const API_ROOT: &str = "https://api.listenbrainz.org/1";
+/// The most listens ListenBrainz accepts in a single request
+pub const MAX_IMPORT_LISTENS: usize = 1000;
+
+#[derive(Debug, Clone)]
+pub struct ListenBrainz {
+    client: Agent,
+    token: String,
+}
+
+/// ListenBrainz expects "single" for one finished listen, "import" for several at once
+/// and "playing_now" for the track currently playing (which has no `listened_at`).
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum ListenType {
+    Single,
+    PlayingNow,
+    Import,
+}
+
+#[derive(Serialize, Debug)]
+struct SubmitListensRequest<'a> {
+    listen_type: ListenType,
+    payload: Vec<Listen<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+struct Listen<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    listened_at: Option<u64>,
+    track_metadata: TrackMetadata<'a>,
+}
+
+#[derive(Serialize, Debug)]
+struct TrackMetadata<'a> {
+    artist_name: &'a str,
+    track_name: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    release_name: Option<&'a str>,
+}
+
+impl<'a> SubmitListensRequest<'a> {
+    fn single(info: &'a MediaInfo, timestamp: SystemTime) -> SubmitListensRequest<'a> {
+        SubmitListensRequest {
+            listen_type: ListenType::Single,
+            payload: vec![Listen {
+                listened_at: Some(unix_timestamp(timestamp)),
+                track_metadata: TrackMetadata::from_media_info(info),
+            }],
+        }
+    }
+
+    fn playing_now(info: &'a MediaInfo) -> SubmitListensRequest<'a> {
+        SubmitListensRequest {
+            listen_type: ListenType::PlayingNow,
+            payload: vec![Listen {
+                listened_at: None,
+                track_metadata: TrackMetadata::from_media_info(info),
+            }],
+        }
+    }
+
+    fn import(listens: &'a [import::Listen]) -> SubmitListensRequest<'a> {
+        let payload = listens
+            .iter()
+            .map(|listen| Listen {
+                listened_at: Some(listen.timestamp),
+                track_metadata: TrackMetadata {
+                    artist_name: &listen.artist,
+                    track_name: &listen.track,
+                    release_name: listen.album.as_deref().filter(|album| !album.is_empty()),
+                },
+            })
+            .collect();
+
+        SubmitListensRequest {
+            listen_type: ListenType::Import,
+            payload,
+        }
+    }
+}
+
+impl<'a> TrackMetadata<'a> {
+    fn from_media_info(info: &'a MediaInfo) -> TrackMetadata<'a> {
+        TrackMetadata {
+            artist_name: &info.artist_name,
+            track_name: &info.song_name,
+            release_name: if info.album_name.is_empty() { None } else { Some(&info.album_name) },
+        }
+    }
+}
+
+impl ListenBrainz {
+    pub fn new(client: Agent, token: String) -> ListenBrainz {
+        ListenBrainz { client, token }
+    }
+
+    /// Submits a single finished listen that started at `timestamp`.
+    pub fn submit_listen(&self, info: &MediaInfo, timestamp: SystemTime) -> Result<(), ureq::Error> {
+        self.submit(SubmitListensRequest::single(info, timestamp))
+    }
+
+    /// Submits listens from a file of scrobble history in a single request. ListenBrainz rejects more than
+    /// [MAX_IMPORT_LISTENS] at once, so callers have to split them up into chunks of that size.
+    pub fn import_listens(&self, listens: &[import::Listen]) -> Result<(), ureq::Error> {
+        debug_assert!(listens.len() <= MAX_IMPORT_LISTENS, "too many listens for one request");
+        self.submit(SubmitListensRequest::import(listens))
+    }
+
+    pub fn playing_now(&self, info: &MediaInfo) -> Result<(), ureq::Error> {
+        self.submit(SubmitListensRequest::playing_now(info))
+    }
+
+    fn submit(&self, request: SubmitListensRequest) -> Result<(), ureq::Error> {
+        debug!("{request:?}");
+
+        let mut rep = self
+            .client
+            .post(format!("{API_ROOT}/submit-listens"))
+            .header("Authorization", &format!("Token {}", self.token))
+            .send_json(&request)?;
+        let body = rep.body_mut().read_to_string()?;
+
+        debug!("{body}");
+
+        if rep.status().is_client_error() || rep.status().is_server_error() {
+            return Err(ureq::Error::StatusCode(rep.status().as_u16()));
+        }
+
+        Ok(())
+    }
+}
+
+impl Scrobbler for ListenBrainz {
+    fn name(&self) -> &'static str {
+        "ListenBrainz"
+    }
+
+    // ListenBrainz stores listens as they're submitted, so there's never anything corrected
+    fn now_playing(&self, info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+        self.playing_now(info).map(|_| Corrections::default())
+    }
+
+    fn scrobble(&self, info: &MediaInfo, timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+        self.submit_listen(info, timestamp).map(|_| Corrections::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::testing::media;
+
+    fn json(request: &SubmitListensRequest) -> String {
+        serde_json::to_string(request).unwrap()
+    }
+
+    #[test]
+    fn listen_payloads() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(
+            json(&SubmitListensRequest::single(&media(), timestamp)),
+            r#"{"listen_type":"single","payload":[{"listened_at":1700000000,"track_metadata":{"artist_name":"Catfish and the Bottlemen","track_name":"7","release_name":"The Balcony"}}]}"#
+        );
+
+        let without_album = MediaInfo {
+            album_name: String::new(),
+            ..media()
+        };
+        assert_eq!(
+            json(&SubmitListensRequest::playing_now(&without_album)),
+            r#"{"listen_type":"playing_now","payload":[{"track_metadata":{"artist_name":"Catfish and the Bottlemen","track_name":"7"}}]}"#
+        );
+
+        let listens = [
+            import::Listen {
+                artist: "Catfish and the Bottlemen".to_owned(),
+                track: "7".to_owned(),
+                album: Some("The Balcony".to_owned()),
+                timestamp: 1_700_000_000,
+            },
+            import::Listen {
+                artist: "Catfish and the Bottlemen".to_owned(),
+                track: "Kathleen".to_owned(),
+                album: None,
+                timestamp: 1_700_000_209,
+            },
+        ];
+        assert_eq!(
+            json(&SubmitListensRequest::import(&listens)),
+            r#"{"listen_type":"import","payload":[{"listened_at":1700000000,"track_metadata":{"artist_name":"Catfish and the Bottlemen","track_name":"7","release_name":"The Balcony"}},{"listened_at":1700000209,"track_metadata":{"artist_name":"Catfish and the Bottlemen","track_name":"Kathleen"}}]}"#
+        );
+    }
+}
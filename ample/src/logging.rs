@@ -2,33 +2,124 @@ use std::{
     fs::{self, File, OpenOptions},
     io::{self, Write},
     path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use log::{Level, LevelFilter};
+use flate2::{Compression, write::GzEncoder};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
 use regex::Regex;
-use simplelog::{Color, ColorChoice, CombinedLogger, ConfigBuilder, TermLogger, TerminalMode, WriteLogger};
+use serde::Serialize;
+use simplelog::{Color, ColorChoice, CombinedLogger, ConfigBuilder, SharedLogger, TermLogger, TerminalMode, WriteLogger};
 
-const MAX_FILE_SIZE: u64 = 1_000_000 * 5; // ~5MB
-const MAX_FILES: u64 = 3;
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 1_000_000 * 5; // ~5MB
+pub const DEFAULT_MAX_FILES: u64 = 3;
+
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+/// Buffered lines are written to the log file once there's this many bytes of them
+const FLUSH_THRESHOLD: usize = 8 * 1024;
+/// or once the oldest buffered line is this old. The main loop also flushes the logger after every tick.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How the log files get rotated.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum RotationMode {
+    /// Start a new file once the current one reaches the max file size. Older files are named ample-1.log, ample-2.log, ...
+    #[default]
+    Size,
+    /// Start a new file every day (UTC) named ample-YYYY-MM-DD.log. Files older than max_files days are removed.
+    Daily,
+}
+
+impl FromStr for RotationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "size" => Ok(RotationMode::Size),
+            "daily" => Ok(RotationMode::Daily),
+            other => Err(format!("unknown rotation mode {other:?}, expected \"size\" or \"daily\"")),
+        }
+    }
+}
+
+/// How lines are written to the log file. The terminal always gets the human readable format.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line with timestamp, level, target and message fields
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("unknown log format {other:?}, expected \"text\" or \"json\"")),
+        }
+    }
+}
+
+/// Settings for the log files written by ample.
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Size in bytes a log file can grow to before it's rotated
+    pub max_file_size: u64,
+    /// Number of log files kept, including the active one. In daily mode, this is the number of days kept.
+    pub max_files: u64,
+    pub rotation: RotationMode,
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_files: DEFAULT_MAX_FILES,
+            rotation: RotationMode::default(),
+            format: LogFormat::default(),
+        }
+    }
+}
 
 struct RollingLogger {
     log_dir: PathBuf,
+    // kept open between writes and only replaced when the log rotates
     inner_file: File,
+    // size of inner_file, kept track of here instead of checking its metadata on every flush
+    file_size: u64,
     max_file_size: u64,
     max_files: u64,
+    rotation: RotationMode,
+    // date the current file was opened on, only used for daily rotation
+    current_date: String,
     file_prefix: &'static str,
+    // compiled once since the directory is scanned on every rotation
+    file_pattern: Regex,
     log_buf: Vec<u8>,
+    last_flush: Instant,
 }
 
 impl RollingLogger {
-    fn new(log_dir: PathBuf, file: File, max_file_size: u64, max_files: u64) -> RollingLogger {
+    fn new(log_dir: PathBuf, file: File, max_file_size: u64, max_files: u64, rotation: RotationMode) -> RollingLogger {
+        let file_prefix = "ample";
         RollingLogger {
             log_dir,
+            file_size: file.metadata().map(|metadata| metadata.len()).unwrap_or(0),
             inner_file: file,
             max_file_size,
             max_files,
-            file_prefix: "ample",
-            log_buf: Vec::with_capacity(128),
+            rotation,
+            current_date: log_date(SystemTime::now()),
+            file_prefix,
+            file_pattern: log_file_pattern(file_prefix, rotation),
+            log_buf: Vec::with_capacity(FLUSH_THRESHOLD),
+            last_flush: Instant::now(),
         }
     }
 
@@ -44,27 +135,35 @@ impl RollingLogger {
                 Err(_) => continue,
             };
 
-            let re = Regex::new(&format!(r"{}-?(\d*).log", self.file_prefix)).expect("invalid regex");
-            // If the log file has an ID in its name
-            if let Some(caps) = re.captures(&entry_name) {
-                if let Some(m) = caps.get(1) {
-                    if !m.is_empty() {
-                        let index = match m.as_str().parse::<u64>() {
-                            Ok(i) => i,
-                            Err(_) => continue,
-                        };
+            let Some(caps) = self.file_pattern.captures(&entry_name) else {
+                continue;
+            };
+            let compressed = caps.get(2).is_some();
 
-                        files.push(RollingLogFile { file_id: index });
+            if self.rotation == RotationMode::Daily {
+                let mut log_file = RollingLogFile::dated(&caps[1]);
+                log_file.compressed = compressed;
+                files.push(log_file);
 
-                        continue;
-                    }
-                }
+                continue;
             }
 
-            // If the log file does not have an ID in its name but still matchs "[file_prefix].log"
-            if re.is_match(&entry_name) {
-                files.push(RollingLogFile { file_id: 0 });
-            }
+            // "[file_prefix].log" is the active log, which has an ID of 0 and is never compressed.
+            // Anything else that would end up with that ID (ample-0.log, ample.log.gz) is left alone
+            let file_id = match caps.get(1) {
+                Some(m) => match m.as_str().parse::<u64>() {
+                    Ok(i) if i > 0 => i,
+                    _ => continue,
+                },
+                None if !compressed => 0,
+                None => continue,
+            };
+
+            files.push(RollingLogFile {
+                file_id,
+                date: None,
+                compressed,
+            });
         }
 
         Ok(files)
@@ -79,16 +178,22 @@ impl RollingLogger {
             fs::rename(&log_file_name, temp_file_name)?;
         }
 
-        // increment each log id and rename the temp log files with their new id
+        // increment each log id and rename the temp log files with their new id,
+        // compressing any that aren't yet since none of them are the active log anymore
         for log_file in log_files.iter_mut() {
             let temp_file_name = log_file.create_log_name(&format!("temp-{}", self.file_prefix), &self.log_dir);
             log_file.file_id += 1;
 
-            fs::rename(temp_file_name, log_file.create_log_name(self.file_prefix, &self.log_dir))?;
+            if log_file.compressed {
+                fs::rename(temp_file_name, log_file.create_log_name(self.file_prefix, &self.log_dir))?;
+            } else {
+                log_file.compressed = true;
+                compress_file(&temp_file_name, &log_file.create_log_name(self.file_prefix, &self.log_dir))?;
+            }
         }
 
         // create the index 0 base log
-        let new_inner_file = File::create(self.log_dir.join(format!("{}.log", self.file_prefix)))?;
+        let new_inner_file = open_log(&self.log_dir.join(format!("{}.log", self.file_prefix)))?;
 
         // remove extra log file
         if (log_files.len() + 1) as u64 > self.max_files {
@@ -98,14 +203,47 @@ impl RollingLogger {
 
         Ok(new_inner_file)
     }
+
+    /// Opens (or creates) the log file for the given date.
+    fn open_dated_log(&self, date: &str) -> Result<File, io::Error> {
+        let file_path = RollingLogFile::dated(date).create_log_name(self.file_prefix, &self.log_dir);
+        open_log(&file_path)
+    }
+
+    /// Removes dated log files from more than max_files days ago and compresses
+    /// the remaining ones other than the file for the current date.
+    fn prune_dated_logs(&self) -> Result<(), io::Error> {
+        let cutoff = SystemTime::now()
+            .checked_sub(Duration::from_secs(SECS_PER_DAY * self.max_files))
+            .unwrap_or(UNIX_EPOCH);
+        let cutoff_date = log_date(cutoff);
+
+        for log_file in self.get_log_files()? {
+            // YYYY-MM-DD dates sort the same way as strings
+            let Some(ref date) = log_file.date else {
+                continue;
+            };
+
+            let log_file_name = log_file.create_log_name(self.file_prefix, &self.log_dir);
+            if *date <= cutoff_date {
+                fs::remove_file(log_file_name)?;
+            } else if *date != self.current_date && !log_file.compressed {
+                let mut compressed_file = RollingLogFile::dated(date);
+                compressed_file.compressed = true;
+                compress_file(&log_file_name, &compressed_file.create_log_name(self.file_prefix, &self.log_dir))?;
+            }
+        }
+
+        Ok(())
+    }
 }
 
 impl Write for RollingLogger {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         let amount_written = self.log_buf.write(buf)?;
 
-        // new line!
-        if buf.len() == 1 && *buf.last().unwrap() == b'\n' {
+        // Only write whole lines, and batch them up so logging doesn't mean a write for every line
+        if buf.last() == Some(&b'\n') && (self.log_buf.len() >= FLUSH_THRESHOLD || self.last_flush.elapsed() >= FLUSH_INTERVAL) {
             self.flush()?;
         }
 
@@ -113,36 +251,81 @@ impl Write for RollingLogger {
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        let expected_size = self.inner_file.metadata()?.len() + self.log_buf.len() as u64;
+        self.last_flush = Instant::now();
+        if self.log_buf.is_empty() {
+            return Ok(());
+        }
+
         let drain: Vec<u8> = self.log_buf.drain(..).collect();
 
-        if expected_size < self.max_file_size {
-            self.inner_file.write(&drain)?;
-        } else {
-            let new_file = self.increment_logs(self.get_log_files()?);
-            self.inner_file = new_file.unwrap();
-            self.inner_file.write(&drain)?;
+        match self.rotation {
+            RotationMode::Size => {
+                if self.file_size + drain.len() as u64 >= self.max_file_size {
+                    self.inner_file = self.increment_logs(self.get_log_files()?)?;
+                    self.file_size = 0;
+                }
+            }
+            RotationMode::Daily => {
+                let today = log_date(SystemTime::now());
+                if today != self.current_date {
+                    // replacing the file closes the previous day's log so that it can be compressed
+                    self.inner_file = self.open_dated_log(&today)?;
+                    self.file_size = self.inner_file.metadata()?.len();
+                    self.current_date = today;
+                    self.prune_dated_logs()?;
+                }
+            }
         }
 
+        self.inner_file.write_all(&drain)?;
+        self.file_size += drain.len() as u64;
         self.inner_file.flush()
     }
 }
 
+/// Matches the names of the log files for `rotation`. The first group is the file's ID or date
+/// and the second one is set when the file is compressed.
+/// The whole name has to match so that files like "temp-ample-1.log" or "ample.log.bak" aren't mistaken for logs.
+fn log_file_pattern(prefix: &str, rotation: RotationMode) -> Regex {
+    let prefix = regex::escape(prefix);
+    match rotation {
+        RotationMode::Daily => Regex::new(&format!(r"^{prefix}-(\d{{4}}-\d{{2}}-\d{{2}})\.log(\.gz)?$")),
+        RotationMode::Size => Regex::new(&format!(r"^{prefix}(?:-(\d+))?\.log(\.gz)?$")),
+    }
+    .expect("invalid regex")
+}
+
 struct RollingLogFile {
     file_id: u64,
+    // set for files created by daily rotation, in which case the id isn't used
+    date: Option<String>,
+    // whether the file has been gzipped and ends in .log.gz
+    compressed: bool,
 }
 
 impl RollingLogFile {
+    fn dated(date: &str) -> RollingLogFile {
+        RollingLogFile {
+            file_id: 0,
+            date: Some(date.to_owned()),
+            compressed: false,
+        }
+    }
+
     fn create_log_name(&self, prefix: &str, directory: &Path) -> PathBuf {
-        if self.file_id == 0 {
-            directory.join(format!("{prefix}.log"))
+        let extension = if self.compressed { "log.gz" } else { "log" };
+
+        if let Some(ref date) = self.date {
+            directory.join(format!("{prefix}-{date}.{extension}"))
+        } else if self.file_id == 0 {
+            directory.join(format!("{prefix}.{extension}"))
         } else {
-            directory.join(format!("{prefix}-{}.log", self.file_id))
+            directory.join(format!("{prefix}-{}.{extension}", self.file_id))
         }
     }
 }
 
-fn open_log_file() -> io::Result<RollingLogger> {
+fn open_log_file(config: &LogConfig) -> io::Result<RollingLogger> {
     // Should create something like "/AppData/ample/config/logs" on windows
     // and "~/.config/ample/logs" on linux
     let log_dir = directories::ProjectDirs::from("", "", crate::APP_NAME)
@@ -151,31 +334,462 @@ fn open_log_file() -> io::Result<RollingLogger> {
         .join("logs");
 
     fs::create_dir_all(&log_dir)?;
-    let file_path = log_dir.join("ample.log");
+    let file_path = match config.rotation {
+        RotationMode::Size => log_dir.join("ample.log"),
+        RotationMode::Daily => RollingLogFile::dated(&log_date(SystemTime::now())).create_log_name("ample", &log_dir),
+    };
 
-    Ok(RollingLogger::new(
-        log_dir,
-        OpenOptions::new().append(true).read(true).create(true).open(file_path)?,
-        MAX_FILE_SIZE,
-        MAX_FILES,
-    ))
+    let logger = RollingLogger::new(log_dir, open_log(&file_path)?, config.max_file_size, config.max_files, config.rotation);
+
+    if config.rotation == RotationMode::Daily {
+        logger.prune_dated_logs()?;
+    }
+
+    Ok(logger)
 }
 
-pub fn init_log(log_level: LevelFilter) -> Result<(), io::Error> {
-    let log_file = open_log_file()?;
-    // only possible error is initting twice
-    let _ = CombinedLogger::init(vec![
-        TermLogger::new(
+/// Opens a log file for appending, creating it if needed.
+fn open_log(path: &Path) -> io::Result<File> {
+    OpenOptions::new().append(true).read(true).create(true).open(path)
+}
+
+/// Gzips `source` into `destination` and then removes `source`.
+fn compress_file(source: &Path, destination: &Path) -> Result<(), io::Error> {
+    {
+        let mut input = File::open(source)?;
+        let mut encoder = GzEncoder::new(File::create(destination)?, Compression::default());
+        io::copy(&mut input, &mut encoder)?;
+        encoder.finish()?;
+    }
+
+    fs::remove_file(source)
+}
+
+/// Formats the (UTC) date of `time` as YYYY-MM-DD.
+fn log_date(time: SystemTime) -> String {
+    let days = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / SECS_PER_DAY;
+    let (year, month, day) = civil_from_days(days as i64);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = (if z >= 0 { z } else { z - 146096 }) / 146097;
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = (if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 }) as u32;
+    let year = year_of_era + era * 400 + (if month <= 2 { 1 } else { 0 });
+
+    (year, month, day)
+}
+
+/// Logs each record to stdout as a single logfmt line, e.g.
+/// `time=2025-01-01T12:00:00Z level=INFO target=ample msg="Started listening loop"`.
+/// Used in headless mode, where whatever supervises ample collects its output.
+struct StdoutLogger {
+    level: LevelFilter,
+}
+
+impl Log for StdoutLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_line(SystemTime::now(), record.level(), record.target(), &record.args().to_string());
+        // Nothing sensible to do if stdout is gone
+        let _ = writeln!(io::stdout().lock(), "{line}");
+    }
+
+    fn flush(&self) {
+        let _ = io::stdout().flush();
+    }
+}
+
+fn format_line(time: SystemTime, level: Level, target: &str, message: &str) -> String {
+    format!("time={} level={level} target={target} msg={}", log_timestamp(time), quote_value(message))
+}
+
+/// Quotes a logfmt value, escaping anything that would break it over multiple lines.
+fn quote_value(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// RFC 3339 timestamp in UTC, e.g. 2025-01-01T12:00:00Z
+pub fn log_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((secs / SECS_PER_DAY) as i64);
+    let secs_of_day = secs % SECS_PER_DAY;
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// Writes each record to the log file as a line of JSON.
+struct JsonLogger {
+    level: LevelFilter,
+    writer: Mutex<RollingLogger>,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    timestamp: String,
+    level: &'a str,
+    target: &'a str,
+    message: &'a str,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let line = format_json_line(SystemTime::now(), record.level(), record.target(), &record.args().to_string());
+        let mut writer = self.writer.lock().unwrap();
+        // The newline is written on its own since that's what makes the RollingLogger flush
+        let _ = writer.write_all(line.as_bytes()).and_then(|_| writer.write_all(b"\n"));
+    }
+
+    fn flush(&self) {
+        let _ = self.writer.lock().unwrap().flush();
+    }
+}
+
+impl SharedLogger for JsonLogger {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        self
+    }
+}
+
+fn format_json_line(time: SystemTime, level: Level, target: &str, message: &str) -> String {
+    let line = JsonLine {
+        timestamp: log_timestamp(time),
+        level: level.as_str(),
+        target,
+        message,
+    };
+
+    // Serializing a struct of strings can't fail
+    serde_json::to_string(&line).unwrap_or_default()
+}
+
+/// The level named by AMPLE_LOG_LEVEL (`level`): "error", "warn", "info", "debug", "trace" or "off", in any case.
+/// When it isn't set, AMPLE_DEBUG (`debug`) picks between debug and info like it did before there were levels.
+/// An unknown level is handed back as the error so it can be warned about once logging works.
+pub fn log_level(level: Option<&str>, debug: bool) -> Result<LevelFilter, String> {
+    match level.map(str::trim) {
+        Some(level) => level.parse().map_err(|_| level.to_owned()),
+        None if debug => Ok(LevelFilter::Debug),
+        None => Ok(LevelFilter::Info),
+    }
+}
+
+/// Logs only to stdout, without creating a log file.
+pub fn init_stdout_log(log_level: LevelFilter) -> Result<(), SetLoggerError> {
+    log::set_boxed_logger(Box::new(StdoutLogger { level: log_level }))?;
+    log::set_max_level(log_level);
+
+    Ok(())
+}
+
+pub fn init_log(log_level: LevelFilter, config: &LogConfig) -> Result<(), io::Error> {
+    let log_file = open_log_file(config)?;
+    let mut loggers: Vec<Box<dyn SharedLogger>> = vec![TermLogger::new(
+        log_level,
+        ConfigBuilder::new()
+            .set_location_level(LevelFilter::Debug)
+            .set_level_color(Level::Error, Some(Color::Red))
+            .build(),
+        TerminalMode::Mixed,
+        ColorChoice::Auto,
+    )];
+
+    match config.format {
+        LogFormat::Text => loggers.push(WriteLogger::new(
             log_level,
-            ConfigBuilder::new()
-                .set_location_level(LevelFilter::Debug)
-                .set_level_color(Level::Error, Some(Color::Red))
-                .build(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        ),
-        WriteLogger::new(log_level, ConfigBuilder::new().set_location_level(LevelFilter::Debug).build(), log_file),
-    ]);
+            ConfigBuilder::new().set_location_level(LevelFilter::Debug).build(),
+            log_file,
+        )),
+        LogFormat::Json => loggers.push(Box::new(JsonLogger {
+            level: log_level,
+            writer: Mutex::new(log_file),
+        })),
+    }
+
+    // only possible error is initting twice
+    let _ = CombinedLogger::init(loggers);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, io::Read, process};
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    /// Creates an empty directory for a test to put its logs in.
+    fn log_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ample-logging-{name}-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn size_logger(dir: &Path, max_file_size: u64, max_files: u64) -> RollingLogger {
+        let file = open_log(&dir.join("ample.log")).unwrap();
+        RollingLogger::new(dir.to_path_buf(), file, max_file_size, max_files, RotationMode::Size)
+    }
+
+    fn write_line(logger: &mut RollingLogger, line: &str) {
+        logger.write_all(line.as_bytes()).unwrap();
+        logger.write_all(b"\n").unwrap();
+        logger.flush().unwrap();
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn read_gz(path: &Path) -> String {
+        let mut contents = String::new();
+        GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn log_levels() {
+        assert_eq!(log_level(Some("warn"), false), Ok(LevelFilter::Warn));
+        assert_eq!(log_level(Some(" TRACE "), false), Ok(LevelFilter::Trace));
+        // An explicit level wins over AMPLE_DEBUG
+        assert_eq!(log_level(Some("error"), true), Ok(LevelFilter::Error));
+        assert_eq!(log_level(None, true), Ok(LevelFilter::Debug));
+        assert_eq!(log_level(None, false), Ok(LevelFilter::Info));
+        assert_eq!(log_level(Some("loud"), true), Err("loud".to_owned()));
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(log_date(UNIX_EPOCH + Duration::from_secs(951782400)), "2000-02-29");
+        assert_eq!(log_date(UNIX_EPOCH + Duration::from_secs(1791936000 + 3600)), "2026-10-14");
+    }
+
+    #[test]
+    fn stdout_lines() {
+        let time = UNIX_EPOCH + Duration::from_secs(951782400 + 3661);
+        assert_eq!(log_timestamp(time), "2000-02-29T01:01:01Z");
+
+        assert_eq!(
+            format_line(time, Level::Info, "ample::lastfm", "Got LastFM credentials"),
+            "time=2000-02-29T01:01:01Z level=INFO target=ample::lastfm msg=\"Got LastFM credentials\""
+        );
+        assert_eq!(
+            format_line(time, Level::Error, "ample", "bad \"body\"\nsecond line"),
+            "time=2000-02-29T01:01:01Z level=ERROR target=ample msg=\"bad \\\"body\\\"\\nsecond line\""
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let time = UNIX_EPOCH + Duration::from_secs(951782400 + 3661);
+        assert_eq!(
+            format_json_line(time, Level::Warn, "ample::presence", "a \"quoted\"\nmessage"),
+            r#"{"timestamp":"2000-02-29T01:01:01Z","level":"WARN","target":"ample::presence","message":"a \"quoted\"\nmessage"}"#
+        );
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+    }
+
+    #[test]
+    fn size_rotation() {
+        let dir = log_dir("size");
+        // every line after the first goes over the limit and starts a new file
+        let mut logger = size_logger(&dir, 10, 3);
+
+        write_line(&mut logger, "first");
+        assert_eq!(file_names(&dir), ["ample.log"]);
+
+        write_line(&mut logger, "second");
+        assert_eq!(file_names(&dir), ["ample-1.log.gz", "ample.log"]);
+
+        write_line(&mut logger, "third");
+        assert_eq!(file_names(&dir), ["ample-1.log.gz", "ample-2.log.gz", "ample.log"]);
+
+        // max_files includes the active log, so the oldest one is removed from here on
+        write_line(&mut logger, "fourth");
+        write_line(&mut logger, "fifth");
+        assert_eq!(file_names(&dir), ["ample-1.log.gz", "ample-2.log.gz", "ample.log"]);
+
+        assert_eq!(fs::read_to_string(dir.join("ample.log")).unwrap(), "fifth\n");
+        assert_eq!(read_gz(&dir.join("ample-1.log.gz")), "fourth\n");
+        assert_eq!(read_gz(&dir.join("ample-2.log.gz")), "third\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn rotation_ignores_other_files() {
+        let dir = log_dir("other-files");
+        let others = [
+            "ample-.log",
+            "ample-0.log",
+            "ample.log.gz",
+            "ample.log.bak",
+            "notes.txt",
+            "temp-ample-1.log",
+            "other-2.log",
+        ];
+        for name in others {
+            fs::write(dir.join(name), name).unwrap();
+        }
+
+        let mut logger = size_logger(&dir, 10, 2);
+        for line in ["first", "second", "third"] {
+            write_line(&mut logger, line);
+        }
+
+        let mut expected: Vec<&str> = others.into_iter().chain(["ample.log", "ample-1.log.gz"]).collect();
+        expected.sort();
+        assert_eq!(file_names(&dir), expected);
+        assert_eq!(read_gz(&dir.join("ample-1.log.gz")), "second\n");
+        for name in others {
+            assert_eq!(fs::read_to_string(dir.join(name)).unwrap(), name);
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn daily_pruning() {
+        let dir = log_dir("daily");
+        let today = log_date(SystemTime::now());
+        let yesterday = log_date(SystemTime::now() - Duration::from_secs(SECS_PER_DAY));
+        for name in [
+            "ample-2000-01-01.log",
+            "ample-2000-01-02.log.gz",
+            &format!("ample-{yesterday}.log"),
+            "ample-2000-01-01.txt",
+        ] {
+            fs::write(dir.join(name), "old").unwrap();
+        }
+
+        let file = open_log(&dir.join(format!("ample-{today}.log"))).unwrap();
+        let logger = RollingLogger::new(dir.clone(), file, DEFAULT_MAX_FILE_SIZE, 3, RotationMode::Daily);
+        logger.prune_dated_logs().unwrap();
+
+        let mut expected = vec![
+            "ample-2000-01-01.txt".to_owned(),
+            format!("ample-{today}.log"),
+            format!("ample-{yesterday}.log.gz"),
+        ];
+        expected.sort();
+        assert_eq!(file_names(&dir), expected);
+        assert_eq!(read_gz(&dir.join(format!("ample-{yesterday}.log.gz"))), "old");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn log_file_names() {
+        let sized = log_file_pattern("ample", RotationMode::Size);
+        for name in ["ample.log", "ample-1.log", "ample-12.log.gz"] {
+            assert!(sized.is_match(name), "{name} should match");
+        }
+        for name in [
+            "myample-2.log.bak",
+            "ample-2.logfile",
+            "ample-2xlog",
+            "amplexlog",
+            "ample-.log",
+            "ample.log.gz.tmp",
+            "ample-2024-01-01.log",
+        ] {
+            assert!(!sized.is_match(name), "{name} shouldn't match");
+        }
+
+        let daily = log_file_pattern("ample", RotationMode::Daily);
+        assert_eq!(&daily.captures("ample-2024-01-01.log.gz").unwrap()[1], "2024-01-01");
+        for name in [
+            "ample.log",
+            "ample-2024-01-01xlog",
+            "old-ample-2024-01-01.log",
+            "ample-2024-01-01.log.bak",
+        ] {
+            assert!(!daily.is_match(name), "{name} shouldn't match");
+        }
+
+        // the prefix is matched literally
+        assert!(!log_file_pattern("a.c", RotationMode::Size).is_match("abc.log"));
+    }
+
+    #[test]
+    fn batched_writes() {
+        let dir = log_dir("batched");
+        let mut logger = size_logger(&dir, DEFAULT_MAX_FILE_SIZE, 3);
+
+        logger.write_all(b"first").unwrap();
+        logger.write_all(b"\n").unwrap();
+        assert_eq!(fs::read_to_string(dir.join("ample.log")).unwrap(), "");
+
+        logger.flush().unwrap();
+        assert_eq!(fs::read_to_string(dir.join("ample.log")).unwrap(), "first\n");
+
+        // a full buffer is written without waiting for a flush
+        let line = "a".repeat(FLUSH_THRESHOLD);
+        logger.write_all(line.as_bytes()).unwrap();
+        logger.write_all(b"\n").unwrap();
+        assert_eq!(fs::metadata(dir.join("ample.log")).unwrap().len(), (FLUSH_THRESHOLD + 7) as u64);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,43 +1,80 @@
 #![cfg_attr(feature = "headless", windows_subsystem = "windows")]
+mod ads;
+mod cache;
+mod cli;
+mod config;
+mod coverart;
+mod coverupload;
+mod credfile;
+mod events;
+mod import;
 mod lastfm;
+mod listenbrainz;
 mod logging;
+mod notification;
+mod nowplaying;
+mod presence;
+mod ratelimit;
+mod scrobbler;
 mod secrets;
+mod template;
+#[cfg(test)]
+mod testing;
+mod thumbnail;
 mod uri;
 
 use std::{
+    collections::VecDeque,
     env::VarError,
-    error::Error,
+    fs,
     io::{self, Write},
+    path::Path,
+    sync::Arc,
     thread,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossbeam::select;
-use discord_rich_presence::{
-    activity::{Assets, Timestamps},
-    *,
-};
+use clap::Parser;
+use crossbeam::{channel::Sender, select};
 use log::*;
-use sys_media::{MediaInfo, MediaStatus};
+use sys_media::{MediaInfo, MediaListener, MediaStatus};
 use tray_item::{TIError, TrayItem};
 use ureq::{Agent, config::Config};
 
-use crate::lastfm::{CredsError, LastFm, LastFmCreds};
+use crate::{
+    cli::{Cli, Credential},
+    config::AmpleConfig,
+    coverart::{self, CoverArtArchive, CoverProvider},
+    coverupload::CoverUploader,
+    events::EventSink,
+    lastfm::{CredsError, ImageInfo, LastFm, LastFmCreds},
+    listenbrainz::ListenBrainz,
+    nowplaying::NowPlayingFile,
+    presence::{ActivityFormat, Presence},
+    scrobbler::{self, Corrections, DryRun, ListenTimer, ScrobbleJob, Scrobbler, ScrobblerWorker},
+    thumbnail::ThumbnailServer,
+};
 
-const AMPLE_DPRC_ID: u64 = 1399214780564246670;
 const TICK_TIME: Duration = Duration::from_secs(5);
+// How long --once waits for the LastFM thread to find a cover
+const ONCE_COVER_TIMEOUT: Duration = Duration::from_secs(10);
+// Failed attempts at getting media in a row before reconnecting to the OS. After that it's done again
+// every time the count doubles, since on Windows every reconnect leaks a bit of memory.
+const MEDIA_RECONNECT_AFTER: u32 = 4;
+// How many of the last scrobbles the tray lists
+const TRAY_RECENT_SCROBBLES: usize = 5;
 const APP_NAME: &str = "ample";
 
 #[cfg(feature = "dhat-heap")]
 #[global_allocator]
 static ALLOC: dhat::Alloc = dhat::Alloc;
 
-static mut STOP: bool = false;
-
 fn main() {
     #[cfg(feature = "dhat-heap")]
     let _profiler = dhat::Profiler::new_heap();
 
+    let cli = Cli::parse();
+
     if let Err(err) = dotenvy::dotenv() {
         if err.not_found() {
             info!("No .env file found. Skipping...")
@@ -55,256 +92,1002 @@ fn main() {
         },
     };
 
-    let log_level = if debug { LevelFilter::Debug } else { LevelFilter::Info };
+    let level_var = std::env::var("AMPLE_LOG_LEVEL").ok().filter(|level| !level.trim().is_empty());
+    let level = logging::log_level(level_var.as_deref(), debug);
+    let log_level = level.clone().unwrap_or(LevelFilter::Info);
 
-    logging::init_log(log_level).unwrap();
+    // Logging is set up before checking the config so that config errors end up in the log.
+    // If the config is invalid, the default log settings are used.
+    let config = AmpleConfig::from_env();
+    let log_config = config.as_ref().map(|config| config.log.clone()).unwrap_or_default();
+
+    if cli.headless {
+        // only possible error is initting twice
+        let _ = logging::init_stdout_log(log_level);
+    } else {
+        logging::init_log(log_level, &log_config).unwrap();
+    }
 
     debug!("inited");
+    if let Err(level) = &level {
+        warn!("Unknown AMPLE_LOG_LEVEL {level:?}, logging at info. Expected one of error, warn, info, debug, trace or off");
+    }
+
+    let config = match config {
+        Ok(config) => config,
+        Err(err) => {
+            error!("{err}");
+            if cli.validate_config {
+                log::logger().flush();
+                std::process::exit(1);
+            }
+
+            return;
+        }
+    };
+    debug!("{config:?}");
 
-    let only_am = true;
-    let mut client = get_client();
-    let mut previously_played: Option<MediaInfo> = None;
-    let mut previously_played_started: Option<SystemTime> = None;
-    let mut current_has_been_scrobbled = false;
-    let mut previously_paused = false;
+    if cli.validate_config {
+        let valid = report_config(&config) && level.is_ok();
+        log::logger().flush();
+        if !valid {
+            std::process::exit(1);
+        }
 
-    let media_listener = sys_media::get_listener().unwrap();
+        return;
+    }
 
-    let tray_result = AmpleTray::create();
-    if let Err(ref err) = tray_result {
-        error!("Error while trying to create tray icon: {err}");
+    if config.notifications && !notification::supported() {
+        warn!("AMPLE_NOTIFICATIONS is set but ample was built without the \"notifications\" feature");
     }
 
-    let mut tray = tray_result.ok();
+    if cli.username || cli.password || cli.secret || cli.api_key {
+        let stored = [cli.username, cli.password, cli.secret, cli.api_key].iter().filter(|&&set| set).count();
+        if cli.from_file.is_some() && stored > 1 {
+            error!("--from-file can only store one of --username, --password, --secret and --api-key at a time");
+            return;
+        }
+
+        if cli.username {
+            store_credential(&cli, "LastFM username", "AMPLE_FM_USERNAME", secrets::set_lastfm_username, false);
+        }
+
+        if cli.password {
+            store_credential(&cli, "LastFM password", "AMPLE_FM_PASSWORD", secrets::set_lastfm_password, true);
+        }
+
+        if cli.secret {
+            store_credential(&cli, "LastFM api secret", "AMPLE_FM_SECRET", secrets::set_lastfm_secret, true);
+        }
+
+        if cli.api_key {
+            store_credential(&cli, "LastFM api key", "AMPLE_FM_API_KEY", secrets::set_lastfm_api_key, false);
+        }
+
+        return;
+    }
+
+    let forget = cli.credentials_to_forget();
+    if !forget.is_empty() {
+        for credential in forget {
+            match credential {
+                Credential::Username => report_forgotten("LastFM username", secrets::forget_lastfm_username()),
+                Credential::Password => report_forgotten("LastFM password", secrets::forget_lastfm_password()),
+                Credential::Secret => report_forgotten("LastFM api secret", secrets::forget_lastfm_secret()),
+                Credential::ApiKey => report_forgotten("LastFM api key", secrets::forget_lastfm_api_key()),
+                Credential::Session => report_forgotten("LastFM session", lastfm::forget_session(&config.lastfm_api_root)),
+                Credential::All => unreachable!("credentials_to_forget expands All"),
+            }
+        }
+
+        return;
+    }
+
+    if let Some(ref path) = cli.import_scrobbles {
+        import_scrobbles(&config, path, cli.dry_run);
+        return;
+    }
+
+    if let Some(limit) = cli.recent {
+        if let Some(last_fm) = get_lastfm_creds(&config) {
+            report_recent_tracks(&last_fm, limit);
+        }
+
+        return;
+    }
+
+    if cli.status {
+        let healthy = report_status(&config);
+        log::logger().flush();
+        if !healthy {
+            std::process::exit(1);
+        }
+
+        return;
+    }
+
+    // Ctrl-C and the tray's exit item both stop the main loop through this.
+    // --once exits on its own, so Ctrl-C keeps its default behaviour there
+    let (shutdown_tx, shutdown_rx) = crossbeam::channel::bounded::<()>(1);
+    let ctrlc_tx = shutdown_tx.clone();
+    if !cli.once
+        && let Err(err) = ctrlc::set_handler(move || {
+            // Already shutting down if the channel is full
+            let _ = ctrlc_tx.try_send(());
+        })
+    {
+        error!("Failed to set Ctrl-C handler: {err}");
+    }
+
+    let activity_format = ActivityFormat {
+        default_cover: config.default_cover.clone(),
+        ..ActivityFormat::parse(&config.details_format, &config.state_format)
+    };
+    let presence = Presence::connect(config.discord_reconnect, activity_format);
+    let media_listener = match sys_media::get_listener() {
+        Ok(media_listener) => media_listener,
+        Err(err) => {
+            error!("Media detection isn't available: {err}");
+            return;
+        }
+    };
+    // Getting media is retried every tick and reconnects on its own, so this only makes the problem clear up front
+    if !media_listener.is_available() {
+        warn!("The OS media controls aren't answering, nothing will be detected until they do");
+    }
+
+    // Ticks right away when the player says something changed instead of waiting for the next tick
+    let media_changed_rx = if cli.once {
+        crossbeam::channel::never()
+    } else {
+        let (media_changed_tx, media_changed_rx) = crossbeam::channel::bounded::<()>(1);
+        let on_change = media_listener.on_change(move || {
+            // A tick is already coming if the channel is full
+            let _ = media_changed_tx.try_send(());
+        });
+        match on_change {
+            Ok(()) => media_changed_rx,
+            Err(err) => {
+                warn!("Failed to listen for media changes, only checking every {TICK_TIME:?}: {err}");
+                crossbeam::channel::never()
+            }
+        }
+    };
+
+    // The tray's "All players" item asks the main loop to flip between every player and the configured ones
+    let (all_players_tx, all_players_rx) = crossbeam::channel::bounded::<()>(1);
+    // There's nothing to interact with when only running once or headless
+    let tray = if cli.once || cli.headless || !config.tray {
+        None
+    } else {
+        let tray_result = AmpleTray::create(shutdown_tx.clone(), all_players_tx);
+        if let Err(ref err) = tray_result {
+            error!("Error while trying to create tray icon: {err}");
+        }
+
+        tray_result.ok()
+    };
 
-    let mut current_song_img = String::new();
     let (last_fm_tx, last_fm_rx) = crossbeam::channel::bounded::<LastFmThreadMessage>(1);
-    let (song_img_tx, song_img_rx) = crossbeam::channel::bounded::<String>(1);
+    // Unbounded so the LastFM thread never ends up waiting on the main thread while the main thread waits on it
+    let (presence_tx, presence_rx) = crossbeam::channel::unbounded::<PresenceUpdate>();
 
-    let last_fm = get_lastfm_creds();
-    if let Some(ref l) = last_fm {
-        let inner_last_fm = l.clone();
+    let mut scrobblers: Vec<Arc<dyn Scrobbler>> = Vec::new();
+    // Without any scrobblers the LastFM thread isn't started either, so covers aren't looked up
+    if cli.offline {
+        info!("Offline, nothing will be scrobbled and no covers will be looked up");
+    } else {
+        if let Some(last_fm) = get_lastfm_creds(&config) {
+            if config.check_lastfm_session {
+                check_lastfm_session(&last_fm);
+            }
+            scrobblers.push(Arc::new(last_fm));
+        }
+        if let Some(listen_brainz) = get_listenbrainz(&config) {
+            scrobblers.push(Arc::new(listen_brainz));
+        }
+    }
+
+    if cli.dry_run {
+        info!("Dry run, nothing will be scrobbled");
+        scrobblers = scrobblers
+            .into_iter()
+            .map(|scrobbler| Arc::new(DryRun(scrobbler)) as Arc<dyn Scrobbler>)
+            .collect();
+    }
+
+    let scrobbling_enabled = !scrobblers.is_empty();
+    let last_fm_thread = if scrobbling_enabled {
+        let cover_art = CoverArtArchive::new(new_agent(&config));
+        let cover_providers = coverart::parse_providers(&config.cover_providers);
+        let cover_client = new_agent(&config);
+        // Covers are only checked when asked to, since it's another request for every new song
+        let check_covers = config.check_covers;
+        let displayable = move |url: &str| {
+            let usable = !check_covers || coverart::is_displayable_cover(&cover_client, url);
+            if !usable {
+                info!("Not using cover {url} since Discord can't show it");
+            }
+            usable
+        };
+        // Now playing updates and scrobbles are handed to a thread per scrobbler, so each one fails and retries on its own
+        let workers: Vec<ScrobblerWorker> = scrobblers
+            .iter()
+            .map(|scrobbler| {
+                let presence_tx = presence_tx.clone();
+                ScrobblerWorker::spawn(Arc::clone(scrobbler), move |info, corrections| {
+                    send_corrections(&presence_tx, info, corrections)
+                })
+            })
+            .collect();
+        // Cover lookups retry slow and failing services too, so they get their own thread instead of holding up the rest
+        let (cover_tx, cover_rx) = crossbeam::channel::unbounded::<(MediaInfo, Option<String>)>();
+        let cover_presence_tx = presence_tx.clone();
+        thread::spawn(move || {
+            while let Ok(mut job) = cover_rx.recv() {
+                // Only the newest song's cover is worth looking up, the ones before it aren't playing anymore
+                while let Ok(newer) = cover_rx.try_recv() {
+                    job = newer;
+                }
+
+                let (info, embedded_cover) = job;
+                let found = find_cover(&cover_providers, &scrobblers, &cover_art, &info, embedded_cover.as_deref(), &displayable);
+                if let Some(cover_url) = found
+                    && let Err(r_err) = cover_presence_tx.send(PresenceUpdate::Cover(cover_url))
+                {
+                    error!("{r_err}");
+                    return;
+                }
+            }
+        });
         // LastFM thread
         info!("Started LastFM loop");
-        thread::spawn(move || {
+        Some(thread::spawn(move || {
             loop {
                 let result = last_fm_rx.recv();
                 debug!("lastfm thread received message");
                 match result {
                     Ok(msg) => match msg {
                         LastFmThreadMessage::NowPlaying(info) => {
-                            match inner_last_fm.now_playing(&info.artist_name, &info.song_name, Some(&info.album_name)) {
-                                Err(err) => error!("{err}"),
-                                Ok(_) => info!("LastFM Now Playing: {} - {}", info.song_name, info.artist_name),
+                            for worker in &workers {
+                                worker.send(ScrobbleJob::NowPlaying(info.clone()));
                             }
                         }
-                        LastFmThreadMessage::AlbumImg(info) => {
-                            let lf_track_info = inner_last_fm.get_track_info(&info.artist_name, &info.song_name);
-                            match lf_track_info {
-                                Ok(track) => {
-                                    debug!("Got track info from LastFM: {track:?}");
-                                    if let Some(album) = track.album {
-                                        let song_img = album
-                                            .images
-                                            .iter()
-                                            .find(|info| info.size == "large")
-                                            .map(|info| info.url.clone())
-                                            .unwrap_or_default();
-
-                                        if !song_img.is_empty() {
-                                            if let Err(r_err) = song_img_tx.send(song_img) {
-                                                error!("{r_err}");
-                                                return;
-                                            }
-                                        }
-                                    }
-                                }
-                                Err(err) => {
-                                    error!("{err}")
-                                }
+                        LastFmThreadMessage::AlbumImg(info, embedded_cover) => {
+                            if let Err(err) = cover_tx.send((info, embedded_cover)) {
+                                error!("Cannot send to the cover thread: {err}");
                             }
                         }
                         LastFmThreadMessage::Scrobble(info, timestamp) => {
-                            match inner_last_fm.scrobble(&info.artist_name, &info.song_name, timestamp, Some(&info.album_name)) {
-                                Ok(()) => {
-                                    info!("Song, {} by {} has been scrobbled!", info.song_name, info.artist_name);
-                                }
-                                Err(err) => error!("Failed to scrobble current track: {err}"),
+                            for worker in &workers {
+                                worker.send(ScrobbleJob::Scrobble(info.clone(), timestamp));
                             }
                         }
                     },
-                    Err(err) => {
-                        error!("Error trying to read from channel: {err}");
+                    Err(_) => {
+                        // Every sender is gone, so ample is shutting down
+                        debug!("LastFM channel closed, stopping LastFM loop");
+                        for worker in workers {
+                            worker.stop();
+                        }
                         return;
                     }
                 }
             }
-        });
+        }))
+    } else {
+        None
+    };
+
+    let events = config.event_socket.clone().map(EventSink::new);
+    let now_playing_file = if config.now_playing_file {
+        match NowPlayingFile::default_path() {
+            Some(path) => {
+                info!("Writing what's playing to {}", path.display());
+                Some(NowPlayingFile::new(path))
+            }
+            None => {
+                error!("Can't write what's playing, couldn't find the config folder");
+                None
+            }
+        }
+    } else {
+        None
+    };
+    let thumbnail_server = if config.thumbnail_port == 0 {
+        None
+    } else {
+        let public_url = Some(config.thumbnail_url.as_str()).filter(|url| !url.is_empty());
+        match ThumbnailServer::start(config.thumbnail_port, public_url) {
+            Ok(server) => Some(server),
+            Err(err) => {
+                error!("Failed to start serving covers on port {}: {err}", config.thumbnail_port);
+                None
+            }
+        }
+    };
+    let cover_uploader = if config.cover_upload_url.is_empty() || cli.offline {
+        None
+    } else {
+        let presence_tx = presence_tx.clone();
+        Some(CoverUploader::spawn(
+            new_agent(&config),
+            config.cover_upload_url.clone(),
+            secrets::get_cover_upload_token(),
+            config.cover_upload_field.clone(),
+            move |cover_url| {
+                if let Err(err) = presence_tx.send(PresenceUpdate::Cover(cover_url)) {
+                    error!("{err}");
+                }
+            },
+        ))
+    };
+    // There's no next tick to confirm the track on when only running once
+    let track_debounce = if cli.once { Duration::ZERO } else { config.track_debounce };
+    let idle_timeout = if cli.once { Duration::ZERO } else { config.idle_timeout };
+    let mut watcher = MediaWatcher {
+        config,
+        presence,
+        tray,
+        events,
+        now_playing_file,
+        thumbnail_server,
+        cover_uploader,
+        media_listener,
+        media_failures: 0,
+        last_fm_tx,
+        scrobbling_enabled,
+        track_debounce,
+        pending_track: None,
+        idle_timeout,
+        idle_since: None,
+        previously_played: None,
+        previously_played_started: None,
+        all_players: false,
+        listen_timer: ListenTimer::default(),
+        current_has_been_scrobbled: false,
+        deferred_scrobble: None,
+        previously_paused: false,
+        current_song_img: String::new(),
+        corrections: Corrections::default(),
+    };
+
+    if cli.once {
+        watcher.tick();
+
+        // Give the LastFM thread a chance to find a cover for the new song before exiting
+        if watcher.scrobbling_enabled && watcher.previously_played.is_some() {
+            let deadline = Instant::now() + ONCE_COVER_TIMEOUT;
+            loop {
+                match presence_rx.recv_deadline(deadline) {
+                    Ok(PresenceUpdate::Cover(cover_url)) => {
+                        watcher.set_cover(cover_url);
+                        break;
+                    }
+                    Ok(update) => watcher.update_presence(update),
+                    Err(err) => {
+                        debug!("No cover received: {err}");
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Dropping the watcher closes the LastFM channel, which lets the thread finish sending what it has and stop
+        drop(watcher);
+        if let Some(handle) = last_fm_thread
+            && handle.join().is_err()
+        {
+            error!("LastFM thread panicked");
+        }
+
+        return;
     }
 
     // Main thread loop
     info!("Started listening loop");
     loop {
         select! {
-            // Instantly update status cover img when we get it from LastFM
-            recv(song_img_rx) -> msg => {
+            // Instantly update status when we get the cover or corrections from LastFM
+            recv(presence_rx) -> msg => {
                 match msg {
-                    Ok(cover_url) => {
-                        match update_status(&mut client, previously_played.as_ref().expect("Cover update should only happen after a song has started to play"), &cover_url) {
-                            Ok(()) => info!("Status img updated to: {cover_url}"),
-                            Err(err) => error!("Error trying to update status: {err}")
-                        }
-                        current_song_img = cover_url.clone();
-                    },
+                    Ok(update) => watcher.update_presence(update),
                     Err(err) => {
                         error!("Error trying to receive from LastFM thread: {err}");
                         return;
                     }
                 }
             },
+            recv(shutdown_rx) -> _ => {
+                info!("Shutting down");
+                watcher.shutdown();
+                break;
+            },
+            recv(all_players_rx) -> _ => watcher.toggle_all_players(),
+            recv(media_changed_rx) -> _ => {
+                debug!("Player reported a change");
+                watcher.tick();
+            },
             // Otherwise continue checking currently playing song
-            default(TICK_TIME) => {
-                // TODO: Replace this with a Mutex or Channel
-                unsafe {
-                    if STOP {
-                        break;
+            default(TICK_TIME) => watcher.tick(),
+        }
+
+        // The log file only gets written every so often otherwise
+        log::logger().flush();
+    }
+}
+
+/// Everything the main loop keeps track of between ticks.
+struct MediaWatcher {
+    config: AmpleConfig,
+    presence: Presence,
+    tray: Option<AmpleTray>,
+    events: Option<EventSink>,
+    now_playing_file: Option<NowPlayingFile>,
+    thumbnail_server: Option<ThumbnailServer>,
+    cover_uploader: Option<CoverUploader>,
+    media_listener: MediaListener,
+    // Failed attempts at getting media in a row, see MEDIA_RECONNECT_AFTER
+    media_failures: u32,
+    last_fm_tx: Sender<LastFmThreadMessage>,
+    scrobbling_enabled: bool,
+    track_debounce: Duration,
+    // A new track and when it was first seen, waiting to have played for `track_debounce` before it's shown
+    pending_track: Option<(MediaInfo, Instant)>,
+    idle_timeout: Duration,
+    // When nothing started playing anymore, for waiting `idle_timeout` before clearing the presence
+    idle_since: Option<Instant>,
+    previously_played: Option<MediaInfo>,
+    previously_played_started: Option<SystemTime>,
+    // Set from the tray to show and scrobble every player, instead of only the ones in the config
+    all_players: bool,
+    // How much of the current song has been listened to, which is what decides when it's scrobbled
+    listen_timer: ListenTimer,
+    current_has_been_scrobbled: bool,
+    // When the current song is due to be scrobbled with `scrobble_on_completion`, it's sent once the song is over
+    deferred_scrobble: Option<SystemTime>,
+    previously_paused: bool,
+    current_song_img: String,
+    /// Names the scrobblers corrected for the current song, shown instead of what the player reports
+    corrections: Corrections,
+}
+
+impl MediaWatcher {
+    /// Checks what is currently playing and updates the activity, tray and scrobblers to match.
+    fn tick(&mut self) {
+        let mut currently_playing = self.media_listener.get_current_playing_info();
+        if let Ok(Some(ref mut media_info)) = currently_playing {
+            sys_media::normalize_apple_music(media_info);
+        }
+        // let currently_playing: Result<Option<MediaInfo>, MediaError> = Ok(Some(MediaInfo{
+        //     album_name: "Test".to_owned(),
+        //     player_name: APPLE_MUSIC_ID.to_owned(),
+        //     artist_name: "Test".to_owned(),
+        //     current_position: 0,
+        //     end_time: 1000000,
+        //     song_name: "Test Song".to_owned(),
+        //     status: MediaStatus::Playing,
+        //     media_type: sys_media::MediaType::Music
+
+        // }));
+
+        debug!("{currently_playing:#?}");
+
+        if currently_playing.is_ok() && self.media_failures > 0 {
+            info!("Getting media works again after {} failures", self.media_failures);
+            self.media_failures = 0;
+        }
+
+        match currently_playing {
+            Err(error) => {
+                error!("{error}");
+                self.media_failures += 1;
+                if self.media_failures >= MEDIA_RECONNECT_AFTER && self.media_failures.is_power_of_two() {
+                    warn!("Getting media failed {} times in a row, reconnecting", self.media_failures);
+                    match self.media_listener.reconnect() {
+                        Ok(()) => info!("Reconnected to the OS media controls"),
+                        Err(err) => error!("Failed to reconnect to the OS media controls: {err}"),
                     }
                 }
+            }
+            Ok(None) => {
+                debug!("No media is paused or playing!");
+                self.send_deferred_scrobble();
+
+                if let Some(ref mut events) = self.events {
+                    events.send(None);
+                }
+                if let Some(ref mut now_playing_file) = self.now_playing_file {
+                    now_playing_file.write(None, false);
+                }
+
+                if self.idle_long_enough() {
+                    self.presence.clear();
+
+                    if let Some(ref mut tray) = self.tray {
+                        if let Err(error) = tray.clear() {
+                            error!("failed to clear tray status: {error}")
+                        }
+                    }
+                }
+            }
+            Ok(Some(media_info)) => {
+                // Discord and the scrobblers each have their own list of players they care about
+                let show_player = self.all_players || config::player_allowed(&self.config.presence_players, &media_info.player_name);
+                let scrobble_player = self.all_players || config::player_allowed(&self.config.scrobble_players, &media_info.player_name);
 
-                let currently_playing = media_listener.get_current_playing_info();
-                // let currently_playing: Result<Option<MediaInfo>, MediaError> = Ok(Some(MediaInfo{
-                //     album_name: "Test".to_owned(),
-                //     player_name: APPLE_MUSIC_ID.to_owned(),
-                //     artist_name: "Test".to_owned(),
-                //     current_position: 0,
-                //     end_time: 1000000,
-                //     song_name: "Test Song".to_owned(),
-                //     status: MediaStatus::Playing,
-                //     media_type: sys_media::MediaType::Music
-
-                // }));
-
-                debug!("{currently_playing:#?}");
-
-                match currently_playing {
-                    Err(error) => {
-                        if error.is_false_error() {
-                            debug!("No media is paused or playing!");
-                            clear_status(&mut client);
-
-                            if let Some(ref mut tray) = tray {
-                                if let Err(error) = tray.clear() {
-                                    error!("failed to clear tray status: {error}")
-                                }
+                let is_ad = match check_media(self.previously_played.as_ref(), &media_info, &self.config.ad_titles) {
+                    MediaCheck::Use { is_ad } => is_ad,
+                    MediaCheck::Ignore => return,
+                    MediaCheck::IncompleteAd => {
+                        if show_player && self.config.clear_ads && matches!(media_info.status, MediaStatus::Playing) {
+                            self.presence.clear();
+
+                            if let Some(ref mut tray) = self.tray
+                                && let Err(error) = tray.clear()
+                            {
+                                error!("failed to clear tray status: {error}")
                             }
-                        } else {
-                            error!("{error}")
                         }
+                        return;
+                    }
+                };
+                // Videos and such still show up in the presence but shouldn't end up in anyone's listening history
+                let scrobble_eligible =
+                    !is_ad && scrobble_player && self.scrobbling_enabled && self.config.scrobble_media_types.contains(&media_info.media_type);
+
+                if (show_player || scrobble_player)
+                    && let Some(ref mut events) = self.events
+                {
+                    events.send(Some(&media_info));
+                }
+                if show_player || scrobble_player {
+                    self.write_now_playing(&media_info);
+                }
+
+                if let MediaStatus::Playing = media_info.status
+                    && (show_player || scrobble_player)
+                {
+                    self.previously_paused = false;
+                    self.idle_since = None;
+                    let new_song = !self
+                        .previously_played
+                        .as_ref()
+                        .is_some_and(|previous| previous.is_same_track(&media_info));
+                    if new_song && !self.track_settled(&media_info) {
+                        debug!("Waiting to see if {} keeps playing", media_info.song_name);
+                        return;
                     }
-                    Ok(Some(media_info)) => {
-                        let valid_player = !only_am || media_info.player_name == sys_media::consts::APPLE_MUSIC_ID;
-                        if let MediaStatus::Playing = media_info.status
-                            && valid_player
+
+                    if new_song {
+                        self.pending_track = None;
+                        info!("App currently playing {} media: {}", media_info.media_type, media_info.player_name);
+                        info!(
+                            "Currently Playing: {} by {} on {}",
+                            media_info.song_name, media_info.artist_name, media_info.album_name
+                        );
+
+                        // Whatever was playing before has finished now
+                        self.send_deferred_scrobble();
+                        self.current_has_been_scrobbled = false;
+                        self.previously_played_started = Some(SystemTime::now());
+                        self.listen_timer = ListenTimer::default();
+                        self.listen_timer.observe(media_info.position(), Instant::now());
+                        self.previously_played = None;
+                        self.corrections = Corrections::default();
+
+                        if self.config.notifications && show_player && !is_ad {
+                            notification::now_playing(&media_info);
+                        }
+
+                        if is_ad {
+                            info!("{} looks like an ad, not scrobbling it", media_info.song_name);
+                        } else if scrobble_eligible {
+                            let send_err = self.last_fm_tx.send(LastFmThreadMessage::NowPlaying(media_info.clone()));
+                            if let Err(err) = send_err {
+                                error!("Cannot send to LastFM thread: {err}");
+                            }
+                        } else if self.scrobbling_enabled && !scrobble_player {
+                            info!("Not scrobbling media from {}", media_info.player_name);
+                        } else if self.scrobbling_enabled {
+                            info!("Not scrobbling {} media", media_info.media_type);
+                        }
+
+                        let embedded_cover = if show_player && !is_ad { self.embedded_cover(&media_info) } else { None };
+                        if self.scrobbling_enabled && show_player && !is_ad {
+                            // try to get the cover from the player, LastFM or the Cover Art Archive
+                            let send_err = self.last_fm_tx.send(LastFmThreadMessage::AlbumImg(media_info.clone(), embedded_cover));
+                            if let Err(err) = send_err {
+                                error!("Cannot send to LastFM thread: {err}");
+                            }
+                        } else if self.thumbnail_server.is_some() || self.cover_uploader.is_some() {
+                            // Without the LastFM thread the player's cover is the only one there is
+                            self.current_song_img = embedded_cover.unwrap_or_default();
+                        }
+                    } else if scrobble_eligible {
+                        // Try to scrobble current song if we have the creds
+                        let song_len = media_info.length().unwrap_or_default();
+                        let position = media_info.position();
+                        let listened = self.listen_timer.observe(position, Instant::now());
+
+                        if !self.current_has_been_scrobbled
+                            && self.deferred_scrobble.is_none()
+                            && self.config.scrobble_rules.is_due(song_len, listened)
                         {
-                            previously_paused = false;
-                            // New song
-                            if previously_played.as_ref() != Some(&media_info) {
-                                info!("App currently playing media: {}", media_info.player_name);
-                                info!(
-                                    "Currently Playing: {} by {} on {}",
-                                    media_info.song_name, media_info.artist_name, media_info.album_name
-                                );
-
-                                current_has_been_scrobbled = false;
-                                previously_played_started = Some(SystemTime::now());
-                                previously_played = None;
-
-                                // try to get info from LastFM if we have the creds
-                                if last_fm.is_some() {
-                                    let send_err = last_fm_tx.send(LastFmThreadMessage::NowPlaying(media_info.clone()));
-                                    if let Err(err) = send_err {
-                                        error!("Cannot send to LastFM thread: {err}");
-                                    }
-
-                                    let send_err = last_fm_tx.send(LastFmThreadMessage::AlbumImg(media_info.clone()));
-                                    if let Err(err) = send_err {
-                                        error!("Cannot send to LastFM thread: {err}");
-                                    }
-                                }
-                            } else if last_fm.is_some() {
-                                // Try to scrobble current song if we have the creds
-                                let song_len = Duration::from_micros(media_info.end_time as u64);
-                                let duration = Duration::from_micros(media_info.current_position as u64);
-
-                                let song_len_secs = song_len.as_secs();
-
-                                // Per LastFM, scrobbles should only happen for songs longer than 30 secs and
-                                // when the user has listened to atleast half of the song
-                                if song_len_secs > 30 && duration.as_secs() > song_len_secs / 2 && !current_has_been_scrobbled {
-                                    let timestamp = previously_played_started.unwrap_or_else(SystemTime::now);
-                                    match last_fm_tx.send(LastFmThreadMessage::Scrobble(media_info.clone(), timestamp)) {
-                                        Ok(()) => current_has_been_scrobbled = true,
-                                        Err(err) => error!("Cannot send to LastFM thread: {err}"),
-                                    }
-                                }
+                            let timestamp = scrobble_timestamp(self.previously_played_started, position);
+                            if self.config.scrobble_on_completion {
+                                debug!("{} will be scrobbled once it's over", media_info.song_name);
+                                self.deferred_scrobble = Some(timestamp);
+                            } else if self.send_scrobble(&media_info, timestamp) {
+                                self.write_now_playing(&media_info);
                             }
+                        }
+                    }
 
-                            if let Err(error) = update_status(&mut client, &media_info, &current_song_img) {
-                                error!("Error while setting activity: {error}");
-                            } else if previously_played.is_none() {
-                                info!("Activity set to listening to {} - {}", media_info.song_name, media_info.artist_name);
+                    if !show_player || (is_ad && self.config.clear_ads) {
+                        self.presence.clear();
+
+                        if let Some(ref mut tray) = self.tray {
+                            if let Err(error) = tray.clear() {
+                                error!("failed to clear tray status: {error}")
                             }
+                        }
+                    } else {
+                        let shown = self.corrections.apply(&media_info);
+                        if let Err(error) = self.presence.update(&shown, &self.current_song_img) {
+                            error!("Error while setting activity: {error}");
+                        } else if self.previously_played.is_none() {
+                            info!("Activity set to listening to {} - {}", media_info.song_name, media_info.artist_name);
+                        }
 
-                            if let Some(ref mut tray) = tray {
-                                if let Err(error) = tray.update(&media_info) {
-                                    error!("failed to update tray status: {error}");
-                                }
+                        if let Some(ref mut tray) = self.tray {
+                            if let Err(error) = tray.update(&media_info) {
+                                error!("failed to update tray status: {error}");
                             }
+                        }
+                    }
+
+                    self.previously_played = Some(media_info);
+                } else if let MediaStatus::Paused = media_info.status
+                    && show_player
+                    && self.config.show_paused
+                {
+                    self.idle_since = None;
+                    // Only set the paused activity once, it doesn't change until the track is resumed
+                    if !self.previously_paused {
+                        debug!("Media is paused. Showing paused activity");
+
+                        // The fetched cover and corrections belong to whatever was last playing
+                        let (shown, cover_url) = if self.previously_played.as_ref() == Some(&media_info) {
+                            (self.corrections.apply(&media_info), self.current_song_img.as_str())
+                        } else {
+                            (media_info, "")
+                        };
+                        if let Err(error) = self.presence.update(&shown, cover_url) {
+                            error!("Error while setting paused activity: {error}");
+                        }
 
-                            previously_played = Some(media_info);
-                        } else if !previously_paused {
-                            debug!("Media is paused. Clearing activity");
-                            clear_status(&mut client);
+                        self.previously_paused = true;
+                    }
+                } else if !self.previously_paused {
+                    // Paused tracks that aren't shown are cleared right away, anything else that stopped gets the idle timeout
+                    let paused = matches!(media_info.status, MediaStatus::Paused);
+                    if paused || self.idle_long_enough() {
+                        debug!("Media is {}. Clearing activity", media_info.status);
+                        self.presence.clear();
 
-                            if let Some(ref mut tray) = tray {
-                                if let Err(error) = tray.clear() {
-                                    error!("failed to clear tray status: {error}")
-                                }
+                        if let Some(ref mut tray) = self.tray {
+                            if let Err(error) = tray.clear() {
+                                error!("failed to clear tray status: {error}")
                             }
-                            previously_paused = true;
                         }
+                        self.previously_paused = true;
                     }
-                    _ => {}
                 }
             }
         }
     }
+
+    /// Scrobbles the last played song if it was waiting for the song to be over, see `scrobble_on_completion`.
+    fn send_deferred_scrobble(&mut self) {
+        let Some(timestamp) = self.deferred_scrobble.take() else {
+            return;
+        };
+        let Some(media_info) = self.previously_played.clone() else {
+            return;
+        };
+
+        debug!("{} is over, scrobbling it", media_info.song_name);
+        self.send_scrobble(&media_info, timestamp);
+    }
+
+    /// Hands the song to the LastFM thread to be scrobbled and adds it to the tray's recent scrobbles.
+    /// Returns false if the LastFM thread is gone.
+    fn send_scrobble(&mut self, media_info: &MediaInfo, timestamp: SystemTime) -> bool {
+        if let Err(err) = self.last_fm_tx.send(LastFmThreadMessage::Scrobble(media_info.clone(), timestamp)) {
+            error!("Cannot send to LastFM thread: {err}");
+            return false;
+        }
+
+        self.current_has_been_scrobbled = true;
+        if let Some(ref mut tray) = self.tray
+            && let Err(error) = tray.add_recent_scrobble(media_info)
+        {
+            error!("failed to update tray recent scrobbles: {error}");
+        }
+
+        true
+    }
+
+    /// Switches between every player and only the configured ones, then checks again what's playing with the new setting.
+    fn toggle_all_players(&mut self) {
+        self.all_players = !self.all_players;
+        if self.all_players {
+            info!("Showing and scrobbling every player");
+        } else {
+            info!("Only showing and scrobbling the configured players");
+        }
+
+        if let Some(ref mut tray) = self.tray
+            && let Err(error) = tray.set_all_players(self.all_players)
+        {
+            error!("failed to update tray menu: {error}");
+        }
+
+        self.tick();
+    }
+
+    fn write_now_playing(&mut self, media_info: &MediaInfo) {
+        let Some(ref mut now_playing_file) = self.now_playing_file else {
+            return;
+        };
+
+        // The flag is only reset once a new track is confirmed, so it can still belong to the previous one here
+        let scrobbled = self.current_has_been_scrobbled && self.previously_played.as_ref().is_some_and(|previous| previous.is_same_track(media_info));
+        now_playing_file.write(Some(media_info), scrobbled);
+    }
+
+    /// Removes the activity so it doesn't stick around in Discord after ample exits.
+    fn shutdown(&mut self) {
+        self.presence.clear();
+
+        if let Some(ref mut tray) = self.tray
+            && let Err(error) = tray.clear()
+        {
+            error!("failed to clear tray status: {error}")
+        }
+
+        log::logger().flush();
+    }
+
+    fn update_presence(&mut self, update: PresenceUpdate) {
+        match update {
+            PresenceUpdate::Cover(cover_url) => self.set_cover(cover_url),
+            PresenceUpdate::Corrected(info, corrections) => self.set_corrections(&info, corrections),
+        }
+    }
+
+    /// Whether a new track has been playing for at least `track_debounce`, so that tracks skipped right away
+    /// never end up in the presence or get sent as now playing.
+    fn track_settled(&mut self, media_info: &MediaInfo) -> bool {
+        if self.track_debounce.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        match self.pending_track {
+            Some((ref pending, since)) if pending.is_same_track(media_info) => now.duration_since(since) >= self.track_debounce,
+            _ => {
+                self.pending_track = Some((media_info.clone(), now));
+                false
+            }
+        }
+    }
+
+    /// Whether nothing has been playing for at least `idle_timeout`, so that the presence isn't cleared during
+    /// the short gaps between tracks. Counts from the first time it's asked since something last played.
+    fn idle_long_enough(&mut self) -> bool {
+        let idle_since = *self.idle_since.get_or_insert_with(Instant::now);
+        idle_since.elapsed() >= self.idle_timeout
+    }
+
+    /// Where the cover the player shared for the current song can be found, either uploaded or served by ample.
+    /// Covers that still have to be uploaded are shown once they are, so there's no url for them yet.
+    fn embedded_cover(&mut self, media_info: &MediaInfo) -> Option<String> {
+        if self.thumbnail_server.is_none() && self.cover_uploader.is_none() {
+            return None;
+        }
+        if let Some(cover_url) = self.cover_uploader.as_ref().and_then(|uploader| uploader.uploaded_url(media_info)) {
+            return Some(cover_url);
+        }
+
+        let thumbnail = match self.media_listener.get_thumbnail() {
+            Ok(Some(thumbnail)) => Some(thumbnail),
+            Ok(None) => {
+                debug!("The player didn't share a cover");
+                None
+            }
+            Err(err) => {
+                error!("Failed to get the cover from the player: {err}");
+                None
+            }
+        };
+        let Some(thumbnail) = thumbnail else {
+            if let Some(ref thumbnail_server) = self.thumbnail_server {
+                thumbnail_server.clear();
+            }
+            return None;
+        };
+
+        // Uploaded covers work wherever Discord is, so they're preferred over serving them
+        if let Some(ref cover_uploader) = self.cover_uploader {
+            cover_uploader.upload(media_info.clone(), thumbnail);
+            return None;
+        }
+        self.thumbnail_server.as_mut().map(|thumbnail_server| thumbnail_server.serve(thumbnail))
+    }
+
+    /// Shows the cover fetched by the LastFM thread for the current song.
+    fn set_cover(&mut self, cover_url: String) {
+        let Some(media_info) = self.previously_played.as_ref() else {
+            debug!("Received a cover with no song playing");
+            return;
+        };
+
+        match self.presence.update(&self.corrections.apply(media_info), &cover_url) {
+            Ok(()) => info!("Status img updated to: {cover_url}"),
+            Err(err) => error!("Error trying to update status: {err}"),
+        }
+        self.current_song_img = cover_url;
+    }
+
+    /// Shows the names a scrobbler corrected `info` to, as long as it's still the current song.
+    fn set_corrections(&mut self, info: &MediaInfo, corrections: Corrections) {
+        let Some(media_info) = self.previously_played.as_ref() else {
+            return;
+        };
+
+        if media_info != info {
+            debug!("Received corrections for {}, which is no longer playing", info.song_name);
+            return;
+        }
+
+        info!("Corrected to {corrections:?}");
+        self.corrections = corrections;
+        if let Err(err) = self.presence.update(&self.corrections.apply(media_info), &self.current_song_img) {
+            error!("Error trying to update status: {err}");
+        }
+    }
+}
+
+/// Updates from the LastFM thread that change what the presence shows.
+enum PresenceUpdate {
+    Cover(String),
+    Corrected(MediaInfo, Corrections),
+}
+
+fn send_corrections(presence_tx: &Sender<PresenceUpdate>, info: &MediaInfo, corrections: Corrections) {
+    if corrections.is_empty() {
+        return;
+    }
+
+    if let Err(err) = presence_tx.send(PresenceUpdate::Corrected(info.clone(), corrections)) {
+        error!("{err}");
+    }
+}
+
+/// Asks each cover provider in turn for the song's cover, returning the first one that `displayable` accepts.
+fn find_cover(
+    providers: &[CoverProvider],
+    scrobblers: &[Arc<dyn Scrobbler>],
+    cover_art: &CoverArtArchive,
+    info: &MediaInfo,
+    embedded_cover: Option<&str>,
+    displayable: &impl Fn(&str) -> bool,
+) -> Option<String> {
+    let large_image = |images: &[ImageInfo]| {
+        images
+            .iter()
+            .find(|image| image.size == "large")
+            .map(|image| image.url.clone())
+            .filter(|url| !url.is_empty() && displayable(url))
+    };
+    // The id from LastFM saves the Cover Art Archive searching MusicBrainz for the release
+    let mut album_mbid = None;
+
+    for provider in providers {
+        match provider {
+            CoverProvider::Embedded => {
+                if let Some(cover_url) = embedded_cover.filter(|url| displayable(url)) {
+                    return Some(cover_url.to_owned());
+                }
+            }
+            CoverProvider::LastFm => {
+                if let Some(track) = scrobbler::find_track_info(scrobblers, info)
+                    && let Some(album) = track.album
+                {
+                    album_mbid = album.mbid;
+                    if let Some(cover_url) = large_image(&album.images) {
+                        return Some(cover_url);
+                    }
+                }
+
+                // Albums often have art on LastFM even when their tracks don't
+                if !info.album_name.is_empty()
+                    && let Some(album) = scrobbler::find_album_info(scrobblers, info)
+                {
+                    album_mbid = album_mbid.or(album.mbid);
+                    if let Some(cover_url) = large_image(&album.images) {
+                        return Some(cover_url);
+                    }
+                }
+            }
+            CoverProvider::CoverArt if !info.album_name.is_empty() => {
+                let cover = match album_mbid {
+                    Some(ref mbid) => cover_art.get_release_cover_url(mbid),
+                    None => cover_art.get_cover_url(&info.artist_name, &info.album_name),
+                };
+                match cover {
+                    Ok(Some(cover_url)) if displayable(&cover_url) => return Some(cover_url),
+                    Ok(Some(_)) => {}
+                    Ok(None) => debug!("No cover art found for {} by {}", info.album_name, info.artist_name),
+                    Err(err) => error!("Failed to get cover from Cover Art Archive: {err}"),
+                }
+            }
+            CoverProvider::CoverArt => {}
+        }
+
+        debug!("No cover from {provider}, trying the next provider");
+    }
+
+    None
 }
 
 enum LastFmThreadMessage {
     Scrobble(MediaInfo, SystemTime),
     NowPlaying(MediaInfo),
-    AlbumImg(MediaInfo),
+    /// The song and where the cover its player shared is served, if it did
+    AlbumImg(MediaInfo, Option<String>),
 }
 
 struct AmpleTray {
     tray_item: TrayItem,
     status_label_id: u32,
+    all_players_item_id: u32,
+    // Newest first, shown in the labels below with the same index
+    recent_scrobbles: VecDeque<String>,
+    recent_label_ids: Vec<u32>,
 }
 
 impl AmpleTray {
-    fn create() -> Result<AmpleTray, TIError> {
+    fn create(shutdown_tx: Sender<()>, all_players_tx: Sender<()>) -> Result<AmpleTray, TIError> {
         let mut tray = TrayItem::new("Ample", tray_item::IconSource::Resource("ample_icon"))?;
         let id = tray.inner_mut().add_label_with_id("Currently Listening to: Nothing :(")?;
 
         tray.inner_mut().set_tooltip("Ample")?;
-        tray.add_menu_item("Exit", || unsafe {
-            STOP = true;
+        let all_players_item_id = tray.inner_mut().add_menu_item_with_id(&all_players_label(false), move || {
+            // Already being toggled if the channel is full
+            let _ = all_players_tx.try_send(());
+        })?;
+
+        // Labels can't be added in between the others later, so every slot is there from the start
+        tray.add_label("Recent scrobbles:")?;
+        let mut recent_label_ids = Vec::with_capacity(TRAY_RECENT_SCROBBLES);
+        for index in 0..TRAY_RECENT_SCROBBLES {
+            let label = if index == 0 { "Nothing scrobbled yet" } else { "" };
+            recent_label_ids.push(tray.inner_mut().add_label_with_id(label)?);
+        }
+
+        tray.add_menu_item("Exit", move || {
+            let _ = shutdown_tx.try_send(());
         })?;
 
         Ok(AmpleTray {
             tray_item: tray,
             status_label_id: id,
+            all_players_item_id,
+            recent_scrobbles: VecDeque::with_capacity(TRAY_RECENT_SCROBBLES),
+            recent_label_ids,
         })
     }
 
@@ -320,57 +1103,39 @@ impl AmpleTray {
             self.status_label_id,
         )
     }
-}
 
-fn get_client() -> DiscordIpcClient {
-    let mut client = DiscordIpcClient::new(&format!("{AMPLE_DPRC_ID}")).unwrap();
-    // NOTE: Panics because really this entire app can't function without it.
-    // In the future, I'll probably make the error output a bit nicer but still
-    client.connect().unwrap();
+    fn add_recent_scrobble(&mut self, media_info: &MediaInfo) -> Result<(), TIError> {
+        self.recent_scrobbles.truncate(TRAY_RECENT_SCROBBLES - 1);
+        self.recent_scrobbles
+            .push_front(format!("{} by {}", media_info.song_name, media_info.artist_name));
 
-    client
-}
-
-fn update_status(client: &mut DiscordIpcClient, media_info: &MediaInfo, cover_url: &str) -> Result<(), Box<dyn Error>> {
-    let now = SystemTime::now();
-    let dur = now.duration_since(UNIX_EPOCH).expect("epoch should hopefully always be in the past");
-
-    let start_dur = dur.saturating_sub(Duration::from_micros(media_info.current_position as u64));
-    let remaining_time = media_info.end_time - media_info.current_position;
-    let end_dur = dur.saturating_add(Duration::from_micros(remaining_time as u64));
-
-    let state_name = format!("{} - {}", media_info.artist_name, media_info.album_name);
-
-    let mut activity = activity::Activity::new()
-        // TODO: This function fails silently to set the activity when the song title, and thus details, is one of two things:
-        // - Too short
-        // - Starts with a number
-        // I tried to get this to work with the song 7 by the Catfish and the Bottlemen. Thus I don't
-        // know if it fails because of the 7 or because its only 1 character. Need to test this out.
-        .details(&media_info.song_name)
-        .state(&state_name)
-        .activity_type(activity::ActivityType::Listening)
-        .timestamps(Timestamps::new().start(start_dur.as_secs() as i64).end(end_dur.as_secs() as i64));
+        for (scrobble, &id) in self.recent_scrobbles.iter().zip(&self.recent_label_ids) {
+            self.tray_item.inner_mut().set_label(scrobble, id)?;
+        }
 
-    if !cover_url.is_empty() {
-        activity = activity.assets(Assets::new().large_image(cover_url))
+        Ok(())
     }
 
-    debug!("setting status");
-
-    client.set_activity(activity)
+    fn set_all_players(&mut self, all_players: bool) -> Result<(), TIError> {
+        self.tray_item
+            .inner_mut()
+            .set_menu_item_label(&all_players_label(all_players), self.all_players_item_id)
+    }
 }
 
-fn clear_status(client: &mut DiscordIpcClient) {
-    if let Err(err) = client.clear_activity() {
-        error!("Error while clearing activity: {err}");
+/// The tray has no checkboxes, so the "All players" item gets a check mark in its label instead.
+fn all_players_label(all_players: bool) -> String {
+    if all_players {
+        "✓ All players".to_owned()
+    } else {
+        "All players".to_owned()
     }
 }
 
-fn retry_creds(client: Agent, attempts: usize) -> Result<LastFmCreds, CredsError> {
+fn retry_creds(client: Agent, api_root: &str, attempts: usize) -> Result<LastFmCreds, CredsError> {
     let mut creds = None;
-    for _ in 0..attempts {
-        match lastfm::LastFmCreds::get_creds(client.clone()) {
+    for attempt in 1..=attempts {
+        match lastfm::LastFmCreds::get_creds(client.clone(), api_root) {
             Ok(ok_creds) => {
                 creds = Some(ok_creds);
                 break;
@@ -378,7 +1143,11 @@ fn retry_creds(client: Agent, attempts: usize) -> Result<LastFmCreds, CredsError
             Err(err) => {
                 debug!("{err:?}");
                 if let CredsError::RetryableError(_, _) = err {
-                    thread::sleep(Duration::from_secs(1));
+                    if attempt < attempts {
+                        let delay = lastfm::creds_retry_delay(attempt as u32);
+                        debug!("Retrying LastFM login in {delay:?}");
+                        thread::sleep(delay);
+                    }
                     continue;
                 } else {
                     return Err(err);
@@ -393,15 +1162,336 @@ fn retry_creds(client: Agent, attempts: usize) -> Result<LastFmCreds, CredsError
     ))
 }
 
-fn get_lastfm_creds() -> Option<LastFm> {
-    let client = Agent::new_with_config(Config::builder().http_status_as_error(false).build());
+/// Gets a value from where `--from-env` or `--from-file` say, or asks the user for it on stdin, and stores it with `store`.
+/// A `hidden` value isn't shown while it's typed in.
+fn store_credential(cli: &Cli, name: &str, env_var: &str, store: fn(&str) -> Result<(), keyring::Error>, hidden: bool) {
+    let value = if cli.from_env {
+        std::env::var(env_var).map_err(|_| format!("{env_var} isn't set"))
+    } else if let Some(ref path) = cli.from_file {
+        fs::read_to_string(path)
+            .map(|value| value.trim_end_matches(['\r', '\n']).to_owned())
+            .map_err(|err| format!("{}: {err}", path.display()))
+    } else {
+        let prompt = format!("Enter your {name}: ");
+        let input = if hidden {
+            rpassword::prompt_password(prompt)
+        } else {
+            prompted_input(&prompt)
+        };
+        input.map_err(|err| err.to_string())
+    };
+
+    let value = match value {
+        Ok(value) if value.trim().is_empty() => {
+            error!("The {name} is empty, not storing it");
+            return;
+        }
+        Ok(value) => value,
+        Err(err) => {
+            error!("Failed to read {name}: {err}");
+            return;
+        }
+    };
+
+    match store(&value) {
+        Ok(()) => info!("Stored {name} in the credential manager"),
+        Err(err) => error!("Failed to store {name} in the credential manager: {err}"),
+    }
+}
+
+/// Prints `prompt` and reads a line from stdin, without the trailing newline.
+fn prompted_input(prompt: &str) -> io::Result<String> {
+    print!("{prompt}");
+    io::stdout().flush()?;
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input)?;
+
+    Ok(input.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+fn report_forgotten(name: &str, result: Result<bool, keyring::Error>) {
+    match result {
+        Ok(true) => info!("Removed {name} from the credential manager"),
+        Ok(false) => info!("No {name} was stored in the credential manager"),
+        Err(err) => error!("Failed to remove {name} from the credential manager: {err}"),
+    }
+}
+
+/// Logs every setting and whatever's wrong with them for --validate-config. Returns false if anything is.
+/// An unknown AMPLE_LOG_LEVEL has already been warned about by then.
+fn report_config(config: &AmpleConfig) -> bool {
+    info!("Settings: {config:#?}");
+
+    let mut problems = config.problems();
+    if config.notifications && !notification::supported() {
+        problems.push("AMPLE_NOTIFICATIONS is set but ample was built without the \"notifications\" feature".to_owned());
+    }
+
+    for problem in &problems {
+        error!("{problem}");
+    }
+
+    if problems.is_empty() {
+        info!("The settings are valid");
+    }
+
+    problems.is_empty()
+}
+
+/// Checks each part of ample once and logs how it went. Returns false if any check failed.
+fn report_status(config: &AmpleConfig) -> bool {
+    let mut healthy = true;
+
+    let info = sys_media::get_listener().and_then(|listener| {
+        if !listener.is_available() {
+            warn!("Media: the OS media controls aren't answering");
+        }
+        listener.get_current_playing_info()
+    });
+    match info {
+        Ok(Some(mut info)) => {
+            sys_media::normalize_apple_music(&mut info);
+            info!(
+                "Media: {} {} by {} on {} in {}",
+                info.status, info.song_name, info.artist_name, info.album_name, info.player_name
+            )
+        }
+        Ok(None) => info!("Media: nothing playing"),
+        Err(err) => {
+            error!("Media: {err}");
+            healthy = false;
+        }
+    }
+
+    if Presence::connect(config.discord_reconnect, ActivityFormat::default()).is_connected() {
+        info!("Discord: connected");
+    } else {
+        error!("Discord: failed to connect, is Discord open?");
+        healthy = false;
+    }
+
+    // Not everyone scrobbles to LastFM, so it's only checked once a username has been set
+    if secrets::get_lastfm_username().is_none() {
+        info!("LastFM: no username set, skipping");
+    } else {
+        match get_lastfm_creds(config).map(|last_fm| last_fm.get_user_info()) {
+            Some(Ok(user)) => info!("LastFM: logged in as {} with {} scrobbles", user.name, user.playcount.unwrap_or_default()),
+            Some(Err(err)) => {
+                error!("LastFM: credentials didn't work: {err}");
+                healthy = false;
+            }
+            // get_lastfm_creds logs why
+            None => healthy = false,
+        }
+    }
+
+    healthy
+}
+
+/// Logs if the stored LastFM session doesn't work anymore, so it's noticed right away instead of on the first scrobble.
+fn check_lastfm_session(last_fm: &LastFm) {
+    match last_fm.validate_session() {
+        Ok(true) => debug!("LastFM session is valid"),
+        Ok(false) => error!("The stored LastFM session is no longer valid and scrobbles will fail. Run ample with --forget-session to log in again"),
+        Err(err) => warn!("Couldn't check the LastFM session: {err}"),
+    }
+}
+
+fn report_recent_tracks(last_fm: &LastFm, limit: u32) {
+    let tracks = match last_fm.get_recent_tracks(limit) {
+        Ok(tracks) => tracks,
+        Err(err) => {
+            error!("Failed to get recent scrobbles from LastFM: {err}");
+            return;
+        }
+    };
+
+    if tracks.is_empty() {
+        info!("Nothing has been scrobbled yet");
+    }
+
+    for track in tracks {
+        match track.timestamp {
+            Some(timestamp) if !track.now_playing => info!(
+                "Scrobbled {} by {} on {} at {}",
+                track.track,
+                track.artist,
+                track.album,
+                logging::log_timestamp(timestamp)
+            ),
+            _ => info!("Now playing {} by {} on {}", track.track, track.artist, track.album),
+        }
+    }
+}
+
+/// Scrobbles the listens in `path` to LastFM in batches, or only logs them if `dry_run`.
+/// Stops at the first batch that fails so that it's clear which listens are left.
+fn import_scrobbles(config: &AmpleConfig, path: &Path, dry_run: bool) {
+    let listens = match import::read_listens(path) {
+        Ok(listens) => listens,
+        Err(err) => {
+            error!("{err}");
+            return;
+        }
+    };
+
+    info!("Importing {} listens from {}", listens.len(), path.display());
+    if dry_run {
+        for listen in &listens {
+            info!("[dry run] Would scrobble {} by {} at {}", listen.track, listen.artist, listen.timestamp);
+        }
+        return;
+    }
+
+    if let Some(last_fm) = get_lastfm_creds(config) {
+        import_to_lastfm(&last_fm, &listens);
+    }
+    if let Some(listen_brainz) = get_listenbrainz(config) {
+        import_to_listenbrainz(&listen_brainz, &listens);
+    }
+}
+
+fn import_to_lastfm(last_fm: &LastFm, listens: &[import::Listen]) {
+    let (mut accepted, mut ignored) = (0, 0);
+    for (batch_index, batch) in listens.chunks(lastfm::MAX_BATCH_SCROBBLES).enumerate() {
+        let start = batch_index * lastfm::MAX_BATCH_SCROBBLES;
+        match last_fm.scrobble_batch(batch) {
+            Ok((batch_accepted, batch_ignored)) => {
+                accepted += batch_accepted;
+                ignored += batch_ignored;
+                info!("Sent {} of {} listens to LastFM", start + batch.len(), listens.len());
+            }
+            Err(err) => {
+                error!(
+                    "Failed to scrobble listens {} to {} to LastFM, stopping: {err}",
+                    start + 1,
+                    start + batch.len()
+                );
+                break;
+            }
+        }
+    }
+
+    info!("LastFM accepted {accepted} listens and ignored {ignored}");
+    if ignored > 0 {
+        info!("LastFM ignores listens from more than two weeks ago and ones it already has");
+    }
+}
+
+fn import_to_listenbrainz(listen_brainz: &ListenBrainz, listens: &[import::Listen]) {
+    for (batch_index, batch) in listens.chunks(listenbrainz::MAX_IMPORT_LISTENS).enumerate() {
+        let start = batch_index * listenbrainz::MAX_IMPORT_LISTENS;
+        match listen_brainz.import_listens(batch) {
+            Ok(()) => info!("Sent {} of {} listens to ListenBrainz", start + batch.len(), listens.len()),
+            Err(err) => {
+                error!(
+                    "Failed to submit listens {} to {} to ListenBrainz, stopping: {err}",
+                    start + 1,
+                    start + batch.len()
+                );
+                break;
+            }
+        }
+    }
+}
+
+/// What a tick does with the media a player reported, before anything is shown or scrobbled.
+#[derive(Debug, PartialEq)]
+enum MediaCheck {
+    /// The info is complete, `is_ad` says whether it looks like an ad
+    Use { is_ad: bool },
+    /// Names are missing, so there's no reliable info this tick
+    Ignore,
+    /// Names are missing but the title is an ad's, so the activity can be cleared without risking a flicker
+    IncompleteAd,
+}
+
+fn check_media(previous: Option<&MediaInfo>, media_info: &MediaInfo, ad_titles: &[String]) -> MediaCheck {
+    // Apple Music briefly leaves out names while switching tracks. Showing or storing that would make
+    // the real next track look like the same song, or the current one look like a new song
+    if previous.is_some_and(|previous| drops_names(previous, media_info)) {
+        debug!("Ignoring media with missing names: {media_info:?}");
+        return MediaCheck::Ignore;
+    }
+
+    // Discord rejects an activity without details and there's nothing to scrobble without a title and artist,
+    // so a tick where the player left them out counts as having no reliable info at all. A new track's title
+    // often shows up a tick before its artist, so a missing artist alone doesn't make it an ad here
+    if media_info.is_incomplete() {
+        if ads::has_ad_title(media_info, ad_titles) {
+            debug!("Media without an artist has an ad's title: {media_info:?}");
+            return MediaCheck::IncompleteAd;
+        }
+
+        debug!("Ignoring media without a title or artist: {media_info:?}");
+        return MediaCheck::Ignore;
+    }
+
+    MediaCheck::Use {
+        is_ad: ads::is_advertisement(media_info, ad_titles),
+    }
+}
+
+/// Whether `new` is the `previous` track with some of its names left out. Info that fills in a name
+/// `previous` was missing doesn't count, it's the better info and should replace it.
+fn drops_names(previous: &MediaInfo, new: &MediaInfo) -> bool {
+    let dropped = |previous: &str, new: &str| new.is_empty() && !previous.is_empty();
+
+    previous.is_same_track(new)
+        && (dropped(&previous.song_name, &new.song_name)
+            || dropped(&previous.artist_name, &new.artist_name)
+            || dropped(&previous.album_name, &new.album_name))
+}
+
+/// When the current song started playing. Uses the time the song was first seen unless the clock has
+/// since jumped backwards past it, in which case it's worked out from how far into the song the player is.
+fn scrobble_timestamp(started: Option<SystemTime>, position: Duration) -> SystemTime {
+    let now = SystemTime::now();
+    match started {
+        Some(started) if started <= now => started,
+        _ => now.checked_sub(position).unwrap_or(now),
+    }
+}
+
+/// Creates an HTTP client that doesn't treat error statuses as errors, so their bodies can still be read and logged.
+fn new_agent(config: &AmpleConfig) -> Agent {
+    Agent::new_with_config(
+        Config::builder()
+            .http_status_as_error(false)
+            .user_agent(config.user_agent.as_str())
+            .build(),
+    )
+}
+
+fn get_listenbrainz(config: &AmpleConfig) -> Option<ListenBrainz> {
+    match secrets::get_listenbrainz_token() {
+        Some(token) => {
+            info!("Got ListenBrainz token");
+            Some(ListenBrainz::new(new_agent(config), token))
+        }
+        None => {
+            info!("ListenBrainz support not enabled: no user token has been set");
+            None
+        }
+    }
+}
+
+fn get_lastfm_creds(config: &AmpleConfig) -> Option<LastFm> {
+    let client = new_agent(config);
     let retry_attempts = 10;
-    let cred_attempt = retry_creds(client.clone(), retry_attempts);
+    let cred_attempt = retry_creds(client.clone(), &config.lastfm_api_root, retry_attempts);
 
     match cred_attempt {
         Ok(creds) => {
             info!("Got LastFM credentials");
-            Some(lastfm::LastFm::new(client.clone(), creds))
+            Some(lastfm::LastFm::new(
+                client.clone(),
+                creds,
+                &config.lastfm_api_root,
+                config.track_cache_size,
+                config.lastfm_rate_limit,
+            ))
         }
         Err(err) => {
             error!("LastFM support not enabled: {err}");
@@ -409,3 +1499,66 @@ fn get_lastfm_creds() -> Option<LastFm> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::media;
+
+    #[test]
+    fn dropped_names() {
+        let playing = media();
+        let without_album = MediaInfo {
+            album_name: String::new(),
+            ..media()
+        };
+
+        assert!(drops_names(&playing, &without_album));
+        // Apple Music adding the album a moment after the title
+        assert!(!drops_names(&without_album, &playing));
+        assert!(!drops_names(&playing, &playing));
+
+        let next_track = MediaInfo {
+            song_name: "Kathleen".to_owned(),
+            album_name: String::new(),
+            ..media()
+        };
+        assert!(!drops_names(&playing, &next_track));
+    }
+
+    #[test]
+    fn media_checks() {
+        let ad_titles: Vec<String> = ads::DEFAULT_AD_TITLES.map(str::to_owned).to_vec();
+        let playing = media();
+
+        assert_eq!(check_media(None, &playing, &ad_titles), MediaCheck::Use { is_ad: false });
+        assert_eq!(check_media(Some(&playing), &playing, &ad_titles), MediaCheck::Use { is_ad: false });
+
+        // Switching from "7" to "Kathleen", which has its title a tick before its artist
+        let next_track = MediaInfo {
+            song_name: "Kathleen".to_owned(),
+            artist_name: String::new(),
+            ..media()
+        };
+        assert_eq!(check_media(Some(&playing), &next_track, &ad_titles), MediaCheck::Ignore);
+        assert_eq!(check_media(None, &next_track, &ad_titles), MediaCheck::Ignore);
+
+        let without_title = MediaInfo {
+            song_name: String::new(),
+            ..media()
+        };
+        assert_eq!(check_media(Some(&playing), &without_title, &ad_titles), MediaCheck::Ignore);
+
+        let ad = MediaInfo {
+            song_name: "Advertisement".to_owned(),
+            artist_name: String::new(),
+            ..media()
+        };
+        assert_eq!(check_media(Some(&playing), &ad, &ad_titles), MediaCheck::IncompleteAd);
+        let ad_with_artist = MediaInfo {
+            artist_name: "Spotify".to_owned(),
+            ..ad.clone()
+        };
+        assert_eq!(check_media(Some(&playing), &ad_with_artist, &ad_titles), MediaCheck::Use { is_ad: true });
+    }
+}
@@ -0,0 +1,33 @@
+use log::*;
+use sys_media::MediaInfo;
+
+/// Shows a Windows toast for the song that just started playing.
+#[cfg(feature = "notifications")]
+pub fn now_playing(media_info: &MediaInfo) {
+    use tauri_winrt_notification::{Duration, Toast};
+
+    let result = Toast::new(Toast::POWERSHELL_APP_ID)
+        .title(&format!("Now Playing: {} — {}", media_info.song_name, media_info.artist_name))
+        .text1(&media_info.album_name)
+        .duration(Duration::Short)
+        .sound(None)
+        .show();
+
+    if let Err(err) = result {
+        error!("Failed to show now playing notification: {err}");
+    }
+}
+
+/// Notifications need ample to be built with the "notifications" feature, so this only logs.
+#[cfg(not(feature = "notifications"))]
+pub fn now_playing(media_info: &MediaInfo) {
+    debug!(
+        "Not showing notification for {}, ample was built without notifications",
+        media_info.song_name
+    );
+}
+
+/// Whether this build of ample can show notifications.
+pub const fn supported() -> bool {
+    cfg!(feature = "notifications")
+}
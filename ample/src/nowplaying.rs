@@ -0,0 +1,130 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use log::*;
+use serde::Serialize;
+use sys_media::MediaInfo;
+
+/// Keeps a JSON file up to date with what's playing, for widgets and scripts that would rather read a file
+/// than listen on a socket. The file is replaced in one go so readers never see it half written.
+pub struct NowPlayingFile {
+    path: PathBuf,
+    // Last contents written, so the file isn't rewritten every tick
+    last_written: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NowPlaying<'a> {
+    artist: Option<&'a str>,
+    song: Option<&'a str>,
+    album: Option<&'a str>,
+    player: Option<&'a str>,
+    status: String,
+    duration_secs: Option<u64>,
+    scrobbled: bool,
+}
+
+impl NowPlayingFile {
+    pub fn new(path: PathBuf) -> NowPlayingFile {
+        NowPlayingFile { path, last_written: None }
+    }
+
+    /// "now_playing.json" in ample's config folder, e.g. "~/.config/ample/now_playing.json" on linux.
+    pub fn default_path() -> Option<PathBuf> {
+        directories::ProjectDirs::from("", "", crate::APP_NAME).map(|dirs| dirs.config_dir().join("now_playing.json"))
+    }
+
+    /// Writes the current media, or a "stopped" state when nothing is playing. Does nothing if it's the same as last time.
+    /// `scrobbled` is whether the current track has been scrobbled yet.
+    pub fn write(&mut self, media_info: Option<&MediaInfo>, scrobbled: bool) {
+        let contents = now_playing_json(media_info, scrobbled);
+        if self.last_written.as_ref() == Some(&contents) {
+            return;
+        }
+
+        match replace_file(&self.path, &contents) {
+            Ok(()) => {
+                debug!("Wrote now playing to {}", self.path.display());
+                self.last_written = Some(contents);
+            }
+            // It's tried again on the next change
+            Err(err) => error!("Failed to write now playing to {}: {err}", self.path.display()),
+        }
+    }
+}
+
+fn now_playing_json(media_info: Option<&MediaInfo>, scrobbled: bool) -> String {
+    let now_playing = match media_info {
+        Some(info) => NowPlaying {
+            artist: Some(&info.artist_name),
+            song: Some(&info.song_name),
+            album: Some(&info.album_name),
+            player: Some(&info.player_name),
+            status: info.status.to_string(),
+            duration_secs: info.length().map(|length| length.as_secs()).filter(|secs| *secs > 0),
+            scrobbled,
+        },
+        None => NowPlaying {
+            artist: None,
+            song: None,
+            album: None,
+            player: None,
+            status: "stopped".to_owned(),
+            duration_secs: None,
+            scrobbled: false,
+        },
+    };
+
+    // Serializing a struct of strings can't fail
+    serde_json::to_string(&now_playing).unwrap_or_default()
+}
+
+/// Writes `contents` to a temporary file next to `path` and renames it over `path`, which replaces it in one step.
+fn replace_file(path: &Path, contents: &str) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let temp_path = path.with_extension("json.tmp");
+    fs::write(&temp_path, contents)?;
+    fs::rename(temp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use crate::testing::media;
+
+    #[test]
+    fn now_playing_contents() {
+        assert_eq!(
+            now_playing_json(Some(&media()), true),
+            r#"{"artist":"Catfish and the Bottlemen","song":"7","album":"The Balcony","player":"AppleMusic.exe","status":"playing","duration_secs":209,"scrobbled":true}"#
+        );
+        assert_eq!(
+            now_playing_json(None, true),
+            r#"{"artist":null,"song":null,"album":null,"player":null,"status":"stopped","duration_secs":null,"scrobbled":false}"#
+        );
+    }
+
+    #[test]
+    fn replaces_file() {
+        let dir = env::temp_dir().join(format!("ample-now-playing-{}", process::id()));
+        let path = dir.join("now_playing.json");
+
+        let mut file = NowPlayingFile::new(path.clone());
+        file.write(Some(&media()), false);
+        assert!(fs::read_to_string(&path).unwrap().contains(r#""status":"playing""#));
+
+        file.write(None, false);
+        assert!(fs::read_to_string(&path).unwrap().contains(r#""status":"stopped""#));
+        // Only the file itself is left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
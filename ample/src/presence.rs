@@ -0,0 +1,498 @@
+use std::{
+    error::Error,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use discord_rich_presence::{
+    activity::{Assets, Timestamps},
+    *,
+};
+use log::*;
+use sys_media::{MediaInfo, MediaStatus, MediaType};
+
+use crate::template::Template;
+
+const AMPLE_DPRC_ID: u64 = 1399214780564246670;
+const PAUSED_STATE_PREFIX: &str = "⏸ Paused - ";
+
+/// Used for AMPLE_DETAILS_FORMAT and AMPLE_STATE_FORMAT when they aren't set or aren't valid.
+pub const DEFAULT_DETAILS_FORMAT: &str = "{song}";
+pub const DEFAULT_STATE_FORMAT: &str = "{artist} - {album}";
+
+// Discord rejects the whole activity when one of the text fields falls outside of these lengths
+// and the IPC connection doesn't report anything back when that happens.
+const MIN_ACTIVITY_TEXT_LEN: usize = 2;
+const MAX_ACTIVITY_TEXT_LEN: usize = 128;
+// Discord trims whitespace (including zero width spaces) before checking the length,
+// so pad with a blank braille pattern which is invisible but not trimmed.
+const ACTIVITY_TEXT_PADDING: char = '\u{2800}';
+
+/// The Discord connection, along with what it's currently showing.
+pub struct Presence {
+    client: DiscordIpcClient,
+    policy: ReconnectPolicy,
+    format: ActivityFormat,
+    // None while connected
+    reconnect: Option<Reconnect>,
+    // None when nothing is shown
+    shown: Option<ShownActivity>,
+}
+
+/// How often to try connecting to Discord again while it's closed.
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    /// Failed attempts, made every time the activity is updated, before giving up and only trying every `give_up_interval`
+    pub max_retries: u32,
+    pub give_up_interval: Duration,
+}
+
+/// What the activity shows besides the track itself: the two lines of text and the cover used when there is none.
+#[derive(Debug, Clone)]
+pub struct ActivityFormat {
+    pub details: Template,
+    pub state: Template,
+    /// Asset key (uploaded to the Discord app) or image url shown when there's no cover for the track. Empty for none
+    pub default_cover: String,
+}
+
+impl ActivityFormat {
+    /// Parses both templates, warning about and using the default for any that aren't valid.
+    pub fn parse(details: &str, state: &str) -> ActivityFormat {
+        ActivityFormat {
+            details: template_or_default("AMPLE_DETAILS_FORMAT", details, DEFAULT_DETAILS_FORMAT),
+            state: template_or_default("AMPLE_STATE_FORMAT", state, DEFAULT_STATE_FORMAT),
+            default_cover: String::new(),
+        }
+    }
+}
+
+impl Default for ActivityFormat {
+    fn default() -> Self {
+        ActivityFormat::parse(DEFAULT_DETAILS_FORMAT, DEFAULT_STATE_FORMAT)
+    }
+}
+
+fn template_or_default(name: &str, template: &str, default: &str) -> Template {
+    template.parse().unwrap_or_else(|err| {
+        warn!("Invalid {name} {template:?}, using {default:?} instead: {err}");
+        default.parse().expect("default templates should be valid")
+    })
+}
+
+/// Failed attempts at connecting to Discord since the connection was lost.
+#[derive(Debug)]
+struct Reconnect {
+    failures: u32,
+    next_attempt: Instant,
+}
+
+/// The parts of an activity that end up in Discord, kept to skip sending the same activity every tick.
+#[derive(Debug, Clone, PartialEq)]
+struct ShownActivity {
+    details: String,
+    state: String,
+    cover_url: String,
+    /// Shown as "Watching" instead of "Listening to"
+    watching: bool,
+    /// Start and end in seconds since the epoch. None while paused
+    timestamps: Option<(i64, Option<i64>)>,
+}
+
+impl Presence {
+    /// Connects to Discord. If Discord isn't open, connecting is tried again whenever the activity changes.
+    pub fn connect(policy: ReconnectPolicy, format: ActivityFormat) -> Presence {
+        let client = DiscordIpcClient::new(&format!("{AMPLE_DPRC_ID}")).expect("client id should be valid");
+        let mut presence = Presence {
+            client,
+            policy,
+            format,
+            reconnect: Some(Reconnect::new(Instant::now())),
+            shown: None,
+        };
+        presence.ensure_connected();
+
+        presence
+    }
+
+    /// Shows `media_info` as the activity, unless that's what Discord is already showing.
+    /// Does nothing while Discord is closed.
+    pub fn update(&mut self, media_info: &MediaInfo, cover_url: &str) -> Result<(), Box<dyn Error>> {
+        let activity = ShownActivity::new(media_info, cover_url, SystemTime::now(), &self.format);
+        if let Some(ref shown) = self.shown
+            && shown.matches(&activity)
+        {
+            debug!("status unchanged");
+            return Ok(());
+        }
+
+        if !self.ensure_connected() {
+            return Ok(());
+        }
+
+        debug!("setting status");
+
+        if let Err(err) = self.client.set_activity(activity.to_activity()) {
+            self.disconnected(err.as_ref());
+            return Err(err);
+        }
+        self.shown = Some(activity);
+
+        Ok(())
+    }
+
+    /// Whether the last attempt at talking to Discord worked.
+    pub fn is_connected(&self) -> bool {
+        self.reconnect.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        if self.shown.take().is_none() || self.reconnect.is_some() {
+            return;
+        }
+
+        if let Err(err) = self.client.clear_activity() {
+            error!("Error while clearing activity: {err}");
+            self.disconnected(err.as_ref());
+        }
+    }
+
+    /// Whether Discord is connected, connecting again first if it's time to.
+    fn ensure_connected(&mut self) -> bool {
+        let Some(ref mut reconnect) = self.reconnect else {
+            return true;
+        };
+
+        let now = Instant::now();
+        if !reconnect.ready(now) {
+            return false;
+        }
+
+        match self.client.connect() {
+            Ok(()) => {
+                info!("Connected to Discord");
+                self.reconnect = None;
+                true
+            }
+            Err(err) => {
+                if reconnect.failed(now, &self.policy) {
+                    warn!(
+                        "Failed to connect to Discord {} times, only trying again every {}s: {err}",
+                        reconnect.failures,
+                        self.policy.give_up_interval.as_secs()
+                    );
+                } else if reconnect.failures > self.policy.max_retries {
+                    // Already said it's given up, Discord is most likely closed on purpose
+                    debug!("Failed to connect to Discord: {err}");
+                } else {
+                    warn!("Failed to connect to Discord: {err}");
+                }
+
+                false
+            }
+        }
+    }
+
+    fn disconnected(&mut self, err: &dyn Error) {
+        warn!("Lost connection to Discord: {err}");
+        self.reconnect = Some(Reconnect::new(Instant::now()));
+        self.shown = None;
+    }
+}
+
+impl Reconnect {
+    fn new(now: Instant) -> Reconnect {
+        Reconnect {
+            failures: 0,
+            next_attempt: now,
+        }
+    }
+
+    fn ready(&self, now: Instant) -> bool {
+        now >= self.next_attempt
+    }
+
+    /// Records a failed attempt, returning true if it's the one that made ample give up.
+    fn failed(&mut self, now: Instant, policy: &ReconnectPolicy) -> bool {
+        self.failures += 1;
+        if self.failures >= policy.max_retries {
+            self.next_attempt = now + policy.give_up_interval;
+        }
+
+        self.failures == policy.max_retries
+    }
+}
+
+impl ShownActivity {
+    fn new(media_info: &MediaInfo, cover_url: &str, now: SystemTime, format: &ActivityFormat) -> ShownActivity {
+        let paused = matches!(media_info.status, MediaStatus::Paused);
+
+        let details = activity_text("details", &format.details.render(media_info));
+        let state_name = format.state.render(media_info);
+        let state = if paused {
+            activity_text("state", &format!("{PAUSED_STATE_PREFIX}{state_name}"))
+        } else {
+            activity_text("state", &state_name)
+        };
+
+        // Discord counts the elapsed time up from the start timestamp on its own, so the only way to
+        // freeze the progress of a paused track is to leave the timestamps out entirely
+        let timestamps = (!paused).then(|| {
+            // A clock set before 1970 only makes the timestamps wrong, which isn't worth crashing over
+            let dur = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+            let position = media_info.position();
+            let start_dur = dur.saturating_sub(position);
+            // Players that don't know the length report 0 (or less, for some live streams), which would put the end
+            // before the start. Those only get the start, which Discord shows as the time elapsed
+            let end_dur = media_info.length().map(|length| dur.saturating_add(length.saturating_sub(position)));
+
+            (start_dur.as_secs() as i64, end_dur.map(|end_dur| end_dur.as_secs() as i64))
+        });
+
+        ShownActivity {
+            details,
+            state,
+            cover_url: if cover_url.is_empty() { &format.default_cover } else { cover_url }.to_owned(),
+            watching: media_info.media_type == MediaType::Video,
+            timestamps,
+        }
+    }
+
+    /// Whether both would look the same in Discord. The timestamps are worked out from the player's position
+    /// so they can be a second off between ticks even when the song hasn't been seeked.
+    fn matches(&self, other: &ShownActivity) -> bool {
+        let close = |time: i64, other: i64| time.abs_diff(other) <= 1;
+        let timestamps_match = match (self.timestamps, other.timestamps) {
+            (Some((start, end)), Some((other_start, other_end))) => {
+                close(start, other_start)
+                    && match (end, other_end) {
+                        (Some(end), Some(other_end)) => close(end, other_end),
+                        (None, None) => true,
+                        _ => false,
+                    }
+            }
+            (None, None) => true,
+            _ => false,
+        };
+
+        timestamps_match
+            && self.details == other.details
+            && self.state == other.state
+            && self.cover_url == other.cover_url
+            && self.watching == other.watching
+    }
+
+    fn to_activity(&self) -> activity::Activity<'_> {
+        let mut activity = activity::Activity::new()
+            .details(&self.details)
+            .state(&self.state)
+            .activity_type(if self.watching {
+                activity::ActivityType::Watching
+            } else {
+                activity::ActivityType::Listening
+            });
+
+        if let Some((start, end)) = self.timestamps {
+            let mut timestamps = Timestamps::new().start(start);
+            if let Some(end) = end {
+                timestamps = timestamps.end(end);
+            }
+            activity = activity.timestamps(timestamps);
+        }
+
+        if !self.cover_url.is_empty() {
+            activity = activity.assets(Assets::new().large_image(&self.cover_url))
+        }
+
+        activity
+    }
+}
+
+/// Pads or truncates text so that Discord will accept it as an activity field.
+fn activity_text(field: &str, text: &str) -> String {
+    let len = text.chars().count();
+
+    if len < MIN_ACTIVITY_TEXT_LEN {
+        warn!("Activity {field} {text:?} is too short for Discord, padding it");
+
+        let mut padded = text.to_string();
+        padded.extend(std::iter::repeat_n(ACTIVITY_TEXT_PADDING, MIN_ACTIVITY_TEXT_LEN - len));
+        padded
+    } else if len > MAX_ACTIVITY_TEXT_LEN {
+        warn!("Activity {field} {text:?} is too long for Discord, truncating it");
+
+        let mut truncated: String = text.chars().take(MAX_ACTIVITY_TEXT_LEN - 1).collect();
+        truncated.push('…');
+        truncated
+    } else {
+        text.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn short_activity_text() {
+        assert_eq!(activity_text("details", "7"), "7\u{2800}");
+        assert_eq!(activity_text("details", ""), "\u{2800}\u{2800}");
+        assert_eq!(activity_text("details", "22"), "22");
+    }
+
+    #[test]
+    fn long_activity_text() {
+        let long = "a".repeat(200);
+        let truncated = activity_text("state", &long);
+
+        assert_eq!(truncated.chars().count(), MAX_ACTIVITY_TEXT_LEN);
+        assert!(truncated.ends_with('…'));
+        assert_eq!(activity_text("state", &long[..128]), &long[..128]);
+    }
+
+    fn media(status: MediaStatus, current_position: i64) -> MediaInfo {
+        MediaInfo {
+            current_position,
+            status,
+            ..testing::media()
+        }
+    }
+
+    #[test]
+    fn unchanged_activity() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let format = ActivityFormat::default();
+        let shown = ShownActivity::new(&media(MediaStatus::Playing, 10_000_000), "", now, &format);
+        assert_eq!(shown.timestamps, Some((1_700_000_000 - 10, Some(1_700_000_000 + 199))));
+
+        // 5 seconds later and 5 seconds further into the song, give or take a bit of rounding
+        let later = ShownActivity::new(&media(MediaStatus::Playing, 15_400_000), "", now + Duration::from_secs(5), &format);
+        assert!(shown.matches(&later));
+
+        let seeked = ShownActivity::new(&media(MediaStatus::Playing, 60_000_000), "", now + Duration::from_secs(5), &format);
+        assert!(!shown.matches(&seeked));
+
+        let with_cover = ShownActivity::new(&media(MediaStatus::Playing, 10_000_000), "https://example.com/cover.jpg", now, &format);
+        assert!(!shown.matches(&with_cover));
+
+        let paused = ShownActivity::new(&media(MediaStatus::Paused, 10_000_000), "", now, &format);
+        assert_eq!(paused.timestamps, None);
+        assert!(!shown.matches(&paused));
+        assert!(paused.matches(&ShownActivity::new(
+            &media(MediaStatus::Paused, 10_000_000),
+            "",
+            now + Duration::from_secs(60),
+            &format
+        )));
+    }
+
+    #[test]
+    fn windows_timestamps() {
+        // What Windows reports 30 seconds into a 3:29 track, in 100 nanosecond ticks
+        let info = MediaInfo {
+            end_time: sys_media::win_ticks_to_micros(2_090_000_000),
+            current_position: sys_media::win_ticks_to_micros(300_000_000),
+            ..media(MediaStatus::Playing, 0)
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let format = ActivityFormat::default();
+
+        let shown = ShownActivity::new(&info, "", now, &format);
+        assert_eq!(shown.timestamps, Some((1_700_000_000 - 30, Some(1_700_000_000 + 179))));
+
+        // Without a length there's only the time it started at
+        let unknown_length = MediaInfo { end_time: 0, ..info.clone() };
+        let shown = ShownActivity::new(&unknown_length, "", now, &format);
+        assert_eq!(shown.timestamps, Some((1_700_000_000 - 30, None)));
+
+        // Some players keep counting for a moment after the end
+        let past_end = MediaInfo {
+            current_position: 210_000_000,
+            ..info
+        };
+        let shown = ShownActivity::new(&past_end, "", now, &format);
+        assert_eq!(shown.timestamps, Some((1_700_000_000 - 210, Some(1_700_000_000))));
+    }
+
+    #[test]
+    fn stream_timestamps() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let format = ActivityFormat::default();
+
+        // A radio stream that was just tuned into, with neither a length nor a position
+        let radio = MediaInfo {
+            end_time: 0,
+            ..media(MediaStatus::Playing, 0)
+        };
+        assert_eq!(ShownActivity::new(&radio, "", now, &format).timestamps, Some((1_700_000_000, None)));
+
+        let live = MediaInfo {
+            end_time: -1,
+            ..media(MediaStatus::Playing, 90_000_000)
+        };
+        assert_eq!(ShownActivity::new(&live, "", now, &format).timestamps, Some((1_700_000_000 - 90, None)));
+    }
+
+    #[test]
+    fn activity_format() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let shown = ShownActivity::new(&media(MediaStatus::Playing, 0), "", now, &ActivityFormat::default());
+        assert_eq!(shown.details, "7");
+        assert_eq!(shown.state, "Catfish and the Bottlemen - The Balcony");
+
+        assert!(!shown.watching);
+
+        let mut video = media(MediaStatus::Playing, 0);
+        video.media_type = MediaType::Video;
+        assert!(ShownActivity::new(&video, "", now, &ActivityFormat::default()).watching);
+
+        let format = ActivityFormat::parse("{song} by {artist}", "{album}");
+        let shown = ShownActivity::new(&media(MediaStatus::Paused, 0), "", now, &format);
+        assert_eq!(shown.details, "7 by Catfish and the Bottlemen");
+        assert_eq!(shown.state, format!("{PAUSED_STATE_PREFIX}The Balcony"));
+
+        let format = ActivityFormat {
+            default_cover: "music_note".to_owned(),
+            ..ActivityFormat::default()
+        };
+        assert_eq!(
+            ShownActivity::new(&media(MediaStatus::Playing, 0), "", now, &format).cover_url,
+            "music_note"
+        );
+        let cover = "https://example.com/cover.jpg";
+        assert_eq!(ShownActivity::new(&media(MediaStatus::Playing, 0), cover, now, &format).cover_url, cover);
+
+        // Invalid templates fall back to the defaults
+        let format = ActivityFormat::parse("{title}", "{artist");
+        let shown = ShownActivity::new(&media(MediaStatus::Playing, 0), "", now, &format);
+        assert_eq!(shown.details, "7");
+        assert_eq!(shown.state, "Catfish and the Bottlemen - The Balcony");
+    }
+
+    #[test]
+    fn reconnect_give_up() {
+        let policy = ReconnectPolicy {
+            max_retries: 3,
+            give_up_interval: Duration::from_secs(300),
+        };
+        let start = Instant::now();
+        let mut reconnect = Reconnect::new(start);
+        assert!(reconnect.ready(start));
+
+        // Retried straight away until the limit
+        assert!(!reconnect.failed(start, &policy));
+        assert!(!reconnect.failed(start, &policy));
+        assert!(reconnect.ready(start));
+
+        // Then only every give_up_interval, without saying it's given up again
+        assert!(reconnect.failed(start, &policy));
+        assert!(!reconnect.ready(start + Duration::from_secs(299)));
+        assert!(reconnect.ready(start + Duration::from_secs(300)));
+
+        let later = start + Duration::from_secs(300);
+        assert!(!reconnect.failed(later, &policy));
+        assert!(!reconnect.ready(later + Duration::from_secs(10)));
+        assert!(reconnect.ready(later + Duration::from_secs(300)));
+    }
+}
@@ -0,0 +1,94 @@
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::*;
+
+/// Token bucket that keeps requests to a service under `per_second` on average, while still letting a short
+/// burst of up to `per_second` requests through right away. A rate of 0 doesn't limit anything.
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_second: f64,
+    burst: f64,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // Goes below zero when requests are waiting for their turn
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: f64) -> RateLimiter {
+        let burst = per_second.ceil().max(1.0);
+        RateLimiter {
+            per_second,
+            burst,
+            bucket: Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// Blocks until another request can be made.
+    pub fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            debug!("Waiting {wait:?} to stay under {} requests per second", self.per_second);
+            thread::sleep(wait);
+        }
+    }
+
+    /// Takes a token at `now` and returns how long to wait before it can be used.
+    fn reserve(&self, now: Instant) -> Duration {
+        if self.per_second <= 0.0 {
+            return Duration::ZERO;
+        }
+
+        let mut bucket = self.bucket.lock().unwrap();
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.per_second).min(self.burst) - 1.0;
+        bucket.refilled = bucket.refilled.max(now);
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.per_second)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(5.0);
+        let start = Instant::now();
+
+        // The first burst goes through right away, after that each request waits on the one before it
+        for _ in 0..5 {
+            assert_eq!(limiter.reserve(start), Duration::ZERO);
+        }
+        assert_eq!(limiter.reserve(start), Duration::from_millis(200));
+        assert_eq!(limiter.reserve(start), Duration::from_millis(400));
+
+        // Waiting refills the bucket, but only up to the burst size
+        assert_eq!(limiter.reserve(start + Duration::from_secs(60)), Duration::ZERO);
+        for _ in 0..4 {
+            assert_eq!(limiter.reserve(start + Duration::from_secs(60)), Duration::ZERO);
+        }
+        assert!(!limiter.reserve(start + Duration::from_secs(60)).is_zero());
+
+        let unlimited = RateLimiter::new(0.0);
+        for _ in 0..100 {
+            assert_eq!(unlimited.reserve(start), Duration::ZERO);
+        }
+    }
+}
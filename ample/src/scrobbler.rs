@@ -0,0 +1,516 @@
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use crossbeam::channel::{self, Sender};
+use log::*;
+use sys_media::MediaInfo;
+
+use crate::lastfm::{AlbumInfo, TrackInfo};
+
+/// A service that can be told what is currently playing and that keeps a history of listened tracks.
+pub trait Scrobbler: Send + Sync {
+    /// Human readable name of the service, used for logging.
+    fn name(&self) -> &'static str;
+
+    /// Tell the service that the given track has just started playing.
+    /// Returns any corrections the service made to the track's names.
+    fn now_playing(&self, info: &MediaInfo) -> Result<Corrections, ureq::Error>;
+
+    /// Add the given track to the user's listening history. `timestamp` is the time the track started playing.
+    /// Returns any corrections the service made to the track's names.
+    fn scrobble(&self, info: &MediaInfo, timestamp: SystemTime) -> Result<Corrections, ureq::Error>;
+
+    /// Look up what the service knows about the given track, like its album art.
+    /// Services that don't have any track info return `Ok(None)`.
+    fn track_info(&self, _info: &MediaInfo) -> Result<Option<TrackInfo>, ureq::Error> {
+        Ok(None)
+    }
+
+    /// Look up what the service knows about the given track's album, for when `track_info` has no album art.
+    /// Services that don't have any album info return `Ok(None)`.
+    fn album_info(&self, _info: &MediaInfo) -> Result<Option<AlbumInfo>, ureq::Error> {
+        Ok(None)
+    }
+}
+
+/// When a playing track has been listened to for long enough to be scrobbled.
+/// The defaults follow LastFM's rules: tracks longer than 30 seconds, once at least half of it
+/// or 4 minutes have been played, whichever comes first.
+#[derive(Debug, Clone, Copy)]
+pub struct ScrobbleRules {
+    /// Tracks this short or shorter are never scrobbled
+    pub min_length: Duration,
+    /// How much of the track has to be played, between 0 and 1
+    pub fraction: f64,
+    /// Playing this long scrobbles the track even if `fraction` of it hasn't been played yet. Zero turns this off
+    pub max_wait: Duration,
+}
+
+impl Default for ScrobbleRules {
+    fn default() -> Self {
+        ScrobbleRules {
+            min_length: Duration::from_secs(30),
+            fraction: 0.5,
+            max_wait: Duration::from_secs(4 * 60),
+        }
+    }
+}
+
+impl ScrobbleRules {
+    /// Whether a track `length` long that's been played up to `position` should be scrobbled.
+    pub fn is_due(&self, length: Duration, position: Duration) -> bool {
+        if length.as_secs() <= self.min_length.as_secs() {
+            return false;
+        }
+
+        let waited_long_enough = !self.max_wait.is_zero() && position.as_secs() >= self.max_wait.as_secs();
+        position.as_secs() >= length.mul_f64(self.fraction).as_secs() || waited_long_enough
+    }
+}
+
+// How much further the player's position can move than the time that passed, since the two are measured separately
+const SEEK_SLACK: Duration = Duration::from_secs(2);
+
+/// How long the current track has actually been listened to, worked out from how far the player's position moves
+/// between checks. Seeking isn't listening: skipping ahead doesn't scrobble early and going back doesn't start over.
+#[derive(Debug, Default)]
+pub struct ListenTimer {
+    listened: Duration,
+    // Position and when it was seen
+    last: Option<(Duration, Instant)>,
+}
+
+impl ListenTimer {
+    /// Records the player's position at `now` and returns the total time listened so far.
+    pub fn observe(&mut self, position: Duration, now: Instant) -> Duration {
+        if let Some((last_position, last_seen)) = self.last
+            && let Some(moved) = position.checked_sub(last_position)
+            && moved <= now.saturating_duration_since(last_seen) + SEEK_SLACK
+        {
+            self.listened += moved;
+        }
+
+        self.last = Some((position, now));
+        self.listened
+    }
+}
+
+/// Canonical names a service replaced the player's names with, e.g. "the beatles" to "The Beatles".
+/// Names that weren't corrected are `None`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Corrections {
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    pub album: Option<String>,
+}
+
+impl Corrections {
+    pub fn is_empty(&self) -> bool {
+        self.artist.is_none() && self.track.is_none() && self.album.is_none()
+    }
+
+    /// A copy of `info` with the corrected names swapped in.
+    pub fn apply(&self, info: &MediaInfo) -> MediaInfo {
+        let mut corrected = info.clone();
+        if let Some(ref artist) = self.artist {
+            corrected.artist_name = artist.clone();
+        }
+        if let Some(ref track) = self.track {
+            corrected.song_name = track.clone();
+        }
+        if let Some(ref album) = self.album {
+            corrected.album_name = album.clone();
+        }
+
+        corrected
+    }
+}
+
+/// Wraps a scrobbler so that now playing updates and scrobbles are only logged instead of sent.
+/// Track info is still looked up so covers keep working.
+pub struct DryRun(pub Arc<dyn Scrobbler>);
+
+impl Scrobbler for DryRun {
+    fn name(&self) -> &'static str {
+        self.0.name()
+    }
+
+    fn now_playing(&self, info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+        info!(
+            "Dry run, not sending now playing to {}: {} - {}",
+            self.name(),
+            info.song_name,
+            info.artist_name
+        );
+        Ok(Corrections::default())
+    }
+
+    fn scrobble(&self, info: &MediaInfo, timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+        info!(
+            "Dry run, not scrobbling to {}: {} - {} at {}",
+            self.name(),
+            info.song_name,
+            info.artist_name,
+            unix_timestamp(timestamp)
+        );
+        Ok(Corrections::default())
+    }
+
+    fn track_info(&self, info: &MediaInfo) -> Result<Option<TrackInfo>, ureq::Error> {
+        self.0.track_info(info)
+    }
+
+    fn album_info(&self, info: &MediaInfo) -> Result<Option<AlbumInfo>, ureq::Error> {
+        self.0.album_info(info)
+    }
+}
+
+/// What a [ScrobblerWorker] should send to its scrobbler.
+#[derive(Debug, Clone)]
+pub enum ScrobbleJob {
+    NowPlaying(MediaInfo),
+    /// The track and the time it started playing
+    Scrobble(MediaInfo, SystemTime),
+}
+
+/// Sends jobs to a scrobbler on its own thread, so that a service that's slow or down (and being retried)
+/// doesn't hold up the other ones.
+pub struct ScrobblerWorker {
+    // unbounded since scrobbles shouldn't be dropped while the service catches up
+    tx: Sender<ScrobbleJob>,
+    handle: JoinHandle<()>,
+}
+
+impl ScrobblerWorker {
+    /// `on_corrections` is called with the track whenever `scrobbler` corrects any of its names.
+    pub fn spawn(scrobbler: Arc<dyn Scrobbler>, on_corrections: impl Fn(&MediaInfo, Corrections) + Send + 'static) -> ScrobblerWorker {
+        let (tx, rx) = channel::unbounded::<ScrobbleJob>();
+        let handle = thread::spawn(move || {
+            // Ends once every sender is gone, after sending whatever was left
+            for job in rx {
+                match job {
+                    ScrobbleJob::NowPlaying(info) => match scrobbler.now_playing(&info) {
+                        Ok(corrections) => {
+                            info!("{} Now Playing: {} - {}", scrobbler.name(), info.song_name, info.artist_name);
+                            on_corrections(&info, corrections);
+                        }
+                        Err(err) => error!("{}: {err}", scrobbler.name()),
+                    },
+                    ScrobbleJob::Scrobble(info, timestamp) => match scrobbler.scrobble(&info, timestamp) {
+                        Ok(corrections) => {
+                            info!(
+                                "Song, {} by {} has been scrobbled to {}!",
+                                info.song_name,
+                                info.artist_name,
+                                scrobbler.name()
+                            );
+                            on_corrections(&info, corrections);
+                        }
+                        Err(err) => error!("Failed to scrobble current track to {}: {err}", scrobbler.name()),
+                    },
+                }
+            }
+        });
+
+        ScrobblerWorker { tx, handle }
+    }
+
+    pub fn send(&self, job: ScrobbleJob) {
+        // The thread only stops early if the scrobbler panicked, which stop() reports
+        let _ = self.tx.send(job);
+    }
+
+    /// Waits for the jobs that were already sent to finish.
+    pub fn stop(self) {
+        drop(self.tx);
+        if self.handle.join().is_err() {
+            error!("Scrobbler thread panicked");
+        }
+    }
+}
+
+/// Asks each scrobbler in turn about the track, returning the first info found.
+pub fn find_track_info(scrobblers: &[Arc<dyn Scrobbler>], info: &MediaInfo) -> Option<TrackInfo> {
+    for scrobbler in scrobblers {
+        match scrobbler.track_info(info) {
+            Ok(Some(track)) => {
+                debug!("Got track info from {}: {track:?}", scrobbler.name());
+                return Some(track);
+            }
+            Ok(None) => {}
+            Err(err) => error!("Failed to get track info from {}: {err}", scrobbler.name()),
+        }
+    }
+
+    None
+}
+
+/// Asks each scrobbler in turn about the track's album, returning the first answer.
+pub fn find_album_info(scrobblers: &[Arc<dyn Scrobbler>], info: &MediaInfo) -> Option<AlbumInfo> {
+    for scrobbler in scrobblers {
+        match scrobbler.album_info(info) {
+            Ok(Some(album)) => {
+                debug!("Got album info from {}: {album:?}", scrobbler.name());
+                return Some(album);
+            }
+            Ok(None) => {}
+            Err(err) => error!("Failed to get album info from {}: {err}", scrobbler.name()),
+        }
+    }
+
+    None
+}
+
+/// Seconds since the unix epoch for `timestamp`. A misconfigured clock can put times before 1970,
+/// in which case the current time is used instead, or 0 if the clock is that far off too.
+pub fn unix_timestamp(timestamp: SystemTime) -> u64 {
+    match timestamp.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs(),
+        Err(_) => {
+            warn!("Timestamp {timestamp:?} is before the unix epoch, the system clock is probably wrong. Using the current time instead");
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, time::Duration};
+
+    use super::*;
+    use crate::{lastfm::ArtistInfo, testing::media};
+
+    struct MockScrobbler {
+        track_name: Option<&'static str>,
+        fail: bool,
+    }
+
+    impl Scrobbler for MockScrobbler {
+        fn name(&self) -> &'static str {
+            "Mock"
+        }
+
+        fn now_playing(&self, _info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+            Ok(Corrections::default())
+        }
+
+        fn scrobble(&self, _info: &MediaInfo, _timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+            Ok(Corrections::default())
+        }
+
+        fn track_info(&self, info: &MediaInfo) -> Result<Option<TrackInfo>, ureq::Error> {
+            if self.fail {
+                return Err(ureq::Error::StatusCode(500));
+            }
+
+            Ok(self.track_name.map(|name| TrackInfo {
+                name: name.to_owned(),
+                mbid: None,
+                duration_ms: None,
+                playcount: None,
+                userplaycount: None,
+                artist: ArtistInfo {
+                    name: info.artist_name.clone(),
+                    mbid: None,
+                },
+                album: None,
+            }))
+        }
+    }
+
+    #[test]
+    fn first_track_info() {
+        let scrobblers: Vec<Arc<dyn Scrobbler>> = vec![
+            Arc::new(MockScrobbler {
+                track_name: None,
+                fail: true,
+            }),
+            Arc::new(MockScrobbler {
+                track_name: None,
+                fail: false,
+            }),
+            Arc::new(MockScrobbler {
+                track_name: Some("first"),
+                fail: false,
+            }),
+            Arc::new(MockScrobbler {
+                track_name: Some("second"),
+                fail: false,
+            }),
+        ];
+
+        let track = find_track_info(&scrobblers, &media()).unwrap();
+        assert_eq!(track.name, "first");
+        assert_eq!(track.artist.name, "Catfish and the Bottlemen");
+
+        assert!(find_track_info(&scrobblers[..2], &media()).is_none());
+    }
+
+    #[test]
+    fn scrobble_rules() {
+        let rules = ScrobbleRules::default();
+        let secs = Duration::from_secs;
+
+        assert!(!rules.is_due(secs(30), secs(29)));
+        assert!(!rules.is_due(secs(200), secs(99)));
+        assert!(rules.is_due(secs(200), secs(100)));
+
+        // Long tracks are scrobbled after 4 minutes instead of waiting for half of the track
+        assert!(!rules.is_due(secs(20 * 60), secs(239)));
+        assert!(rules.is_due(secs(20 * 60), secs(240)));
+        // Half of a short track comes first
+        assert!(rules.is_due(secs(31), secs(16)));
+
+        let rules = ScrobbleRules {
+            min_length: secs(60),
+            fraction: 0.9,
+            max_wait: Duration::ZERO,
+        };
+        assert!(!rules.is_due(secs(60), secs(59)));
+        assert!(!rules.is_due(secs(20 * 60), secs(10 * 60)));
+        assert!(rules.is_due(secs(200), secs(180)));
+    }
+
+    #[test]
+    fn listen_timer() {
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+        let mut timer = ListenTimer::default();
+
+        // Starting in the middle of a track doesn't count what was played before
+        assert_eq!(timer.observe(secs(60), start), Duration::ZERO);
+        assert_eq!(timer.observe(secs(65), start + secs(5)), secs(5));
+
+        // Seeking forward
+        assert_eq!(timer.observe(secs(180), start + secs(10)), secs(5));
+        assert_eq!(timer.observe(secs(185), start + secs(15)), secs(10));
+
+        // Seeking back
+        assert_eq!(timer.observe(secs(20), start + secs(20)), secs(10));
+        assert_eq!(timer.observe(secs(25), start + secs(25)), secs(15));
+
+        // Paused for a while, then resumed
+        assert_eq!(timer.observe(secs(30), start + secs(600)), secs(20));
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(unix_timestamp(UNIX_EPOCH + Duration::from_secs(1700000000)), 1700000000);
+
+        // Falls back to now, which should be well after the epoch on the machine running the tests
+        assert!(unix_timestamp(UNIX_EPOCH - Duration::from_secs(60)) > 0);
+    }
+
+    #[test]
+    fn apply_corrections() {
+        let corrections = Corrections {
+            artist: Some("The Beatles".to_owned()),
+            ..Default::default()
+        };
+        assert!(!corrections.is_empty());
+        assert!(Corrections::default().is_empty());
+
+        let mut info = media();
+        info.artist_name = "the beatles".to_owned();
+        let corrected = corrections.apply(&info);
+
+        assert_eq!(corrected.artist_name, "The Beatles");
+        assert_eq!(corrected.song_name, info.song_name);
+        assert_eq!(corrected.album_name, info.album_name);
+    }
+
+    /// Panics when anything would be sent to the service
+    struct WriteGuard;
+
+    impl Scrobbler for WriteGuard {
+        fn name(&self) -> &'static str {
+            "Guard"
+        }
+
+        fn now_playing(&self, _info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+            panic!("now playing was sent")
+        }
+
+        fn scrobble(&self, _info: &MediaInfo, _timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+            panic!("scrobble was sent")
+        }
+    }
+
+    #[test]
+    fn dry_run() {
+        let dry_run = DryRun(Arc::new(WriteGuard));
+        assert_eq!(dry_run.name(), "Guard");
+        assert!(dry_run.now_playing(&media()).unwrap().is_empty());
+        assert!(dry_run.scrobble(&media(), SystemTime::now()).unwrap().is_empty());
+
+        let dry_run = DryRun(Arc::new(MockScrobbler {
+            track_name: Some("7"),
+            fail: false,
+        }));
+        assert_eq!(dry_run.track_info(&media()).unwrap().unwrap().name, "7");
+    }
+
+    /// Keeps track of the songs it was sent, or waits for `unblock` before every request
+    struct RecordingScrobbler {
+        sent: Mutex<Vec<String>>,
+        unblock: Option<channel::Receiver<()>>,
+    }
+
+    impl Scrobbler for RecordingScrobbler {
+        fn name(&self) -> &'static str {
+            "Recording"
+        }
+
+        fn now_playing(&self, info: &MediaInfo) -> Result<Corrections, ureq::Error> {
+            if let Some(ref unblock) = self.unblock {
+                let _ = unblock.recv();
+            }
+            self.sent.lock().unwrap().push(format!("now playing {}", info.song_name));
+            Ok(Corrections::default())
+        }
+
+        fn scrobble(&self, info: &MediaInfo, _timestamp: SystemTime) -> Result<Corrections, ureq::Error> {
+            if let Some(ref unblock) = self.unblock {
+                let _ = unblock.recv();
+            }
+            self.sent.lock().unwrap().push(format!("scrobble {}", info.song_name));
+            Err(ureq::Error::StatusCode(500))
+        }
+    }
+
+    #[test]
+    fn workers() {
+        let (unblock_tx, unblock_rx) = channel::unbounded();
+        let slow = Arc::new(RecordingScrobbler {
+            sent: Mutex::default(),
+            unblock: Some(unblock_rx),
+        });
+        let fast = Arc::new(RecordingScrobbler {
+            sent: Mutex::default(),
+            unblock: None,
+        });
+        let slow_worker = ScrobblerWorker::spawn(slow.clone(), |_, _| {});
+        let fast_worker = ScrobblerWorker::spawn(fast.clone(), |_, _| {});
+
+        for worker in [&slow_worker, &fast_worker] {
+            worker.send(ScrobbleJob::NowPlaying(media()));
+            worker.send(ScrobbleJob::Scrobble(media(), SystemTime::now()));
+        }
+
+        // The fast scrobbler isn't held up by the slow one, and a failed scrobble doesn't stop either of them
+        fast_worker.stop();
+        assert_eq!(*fast.sent.lock().unwrap(), ["now playing 7", "scrobble 7"]);
+        assert!(slow.sent.lock().unwrap().is_empty());
+
+        unblock_tx.send(()).unwrap();
+        unblock_tx.send(()).unwrap();
+        slow_worker.stop();
+        assert_eq!(*slow.sent.lock().unwrap(), ["now playing 7", "scrobble 7"]);
+    }
+}
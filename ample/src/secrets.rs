@@ -1,15 +1,36 @@
 use std::env;
 
 use keyring::Entry;
-use log::info;
+use log::{debug, info};
+
+use crate::credfile::{CredentialFile, CredentialFileError};
 
 const SECRET_ENTRY_NAME: &str = "ampleSecret";
 const PASSWORD_ENTRY_NAME: &str = "amplePassword";
+const USERNAME_ENTRY_NAME: &str = "ampleUsername";
+const API_KEY_ENTRY_NAME: &str = "ampleApiKey";
+const LISTENBRAINZ_TOKEN_ENTRY_NAME: &str = "ampleListenBrainzToken";
+const COVER_UPLOAD_TOKEN_ENTRY_NAME: &str = "ampleCoverUploadToken";
+
+/// Attempt to get username from OS password/credential manager. If that fails,
+/// attempt to get environment variable.
+pub fn get_lastfm_username() -> Option<String> {
+    let username_entry = get_entry(USERNAME_ENTRY_NAME);
+
+    match username_entry {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            info!("Failed to get LastFM username from creds manager: {err}");
+            info!("Fall back to environment variable");
+            env::var("AMPLE_FM_USERNAME").ok()
+        }
+    }
+}
 
 /// Attempt to get password from OS password/credential manager. If that fails,
 /// attempt to get environment variable.
 pub fn get_lastfm_password() -> Option<String> {
-    let password_entry = Entry::new_with_target(PASSWORD_ENTRY_NAME, crate::APP_NAME, crate::APP_NAME).and_then(|entry| entry.get_password());
+    let password_entry = get_entry(PASSWORD_ENTRY_NAME);
 
     match password_entry {
         Ok(entry) => Some(entry),
@@ -24,7 +45,7 @@ pub fn get_lastfm_password() -> Option<String> {
 /// Attempt to get API secret from OS password/credential manager. If that fails,
 /// attempt to get environment variable.
 pub fn get_lastfm_secret() -> Option<String> {
-    let secret_entry = Entry::new_with_target(SECRET_ENTRY_NAME, crate::APP_NAME, crate::APP_NAME).and_then(|entry| entry.get_password());
+    let secret_entry = get_entry(SECRET_ENTRY_NAME);
 
     match secret_entry {
         Ok(entry) => Some(entry),
@@ -35,3 +56,163 @@ pub fn get_lastfm_secret() -> Option<String> {
         }
     }
 }
+
+/// Attempt to get API key from OS password/credential manager. If that fails,
+/// attempt to get environment variable.
+pub fn get_lastfm_api_key() -> Option<String> {
+    let api_key_entry = get_entry(API_KEY_ENTRY_NAME);
+
+    match api_key_entry {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            info!("Failed to get LastFM api key from creds manager: {err}");
+            info!("Fall back to environment variable");
+            env::var("AMPLE_FM_API_KEY").ok()
+        }
+    }
+}
+
+/// Attempt to get ListenBrainz user token from OS password/credential manager. If that fails,
+/// attempt to get environment variable.
+pub fn get_listenbrainz_token() -> Option<String> {
+    let token_entry = get_entry(LISTENBRAINZ_TOKEN_ENTRY_NAME);
+
+    match token_entry {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            info!("Failed to get ListenBrainz token from creds manager: {err}");
+            info!("Fall back to environment variable");
+            env::var("AMPLE_LB_TOKEN").ok()
+        }
+    }
+}
+
+/// Attempt to get the image host's token for uploading covers from OS password/credential manager. If that fails,
+/// attempt to get environment variable.
+pub fn get_cover_upload_token() -> Option<String> {
+    let token_entry = get_entry(COVER_UPLOAD_TOKEN_ENTRY_NAME);
+
+    match token_entry {
+        Ok(entry) => Some(entry),
+        Err(err) => {
+            info!("Failed to get cover upload token from creds manager: {err}");
+            info!("Fall back to environment variable");
+            env::var("AMPLE_COVER_UPLOAD_TOKEN").ok()
+        }
+    }
+}
+
+/// Stores the LastFM username in the OS password/credential manager.
+pub fn set_lastfm_username(username: &str) -> Result<(), keyring::Error> {
+    set_entry(USERNAME_ENTRY_NAME, username)
+}
+
+/// Stores the LastFM password in the OS password/credential manager.
+pub fn set_lastfm_password(password: &str) -> Result<(), keyring::Error> {
+    set_entry(PASSWORD_ENTRY_NAME, password)
+}
+
+/// Stores the LastFM api secret in the OS password/credential manager.
+pub fn set_lastfm_secret(secret: &str) -> Result<(), keyring::Error> {
+    set_entry(SECRET_ENTRY_NAME, secret)
+}
+
+/// Stores the LastFM api key in the OS password/credential manager.
+pub fn set_lastfm_api_key(api_key: &str) -> Result<(), keyring::Error> {
+    set_entry(API_KEY_ENTRY_NAME, api_key)
+}
+
+/// Reads an entry from the credential manager, or from ample's [CredentialFile] when there's no credential manager to use
+/// or the file was asked for (see [file_chosen]).
+pub fn get_entry(entry_name: &str) -> Result<String, keyring::Error> {
+    if file_chosen() {
+        return credentials_file()?.get(entry_name).map_err(file_error)?.ok_or(keyring::Error::NoEntry);
+    }
+
+    match Entry::new_with_target(entry_name, crate::APP_NAME, crate::APP_NAME).and_then(|entry| entry.get_password()) {
+        Err(err) if keyring_unavailable(&err) => {
+            debug!("No credential manager to get {entry_name} from ({err}), using the credentials file");
+            credentials_file()?.get(entry_name).map_err(file_error)?.ok_or(keyring::Error::NoEntry)
+        }
+        result => result,
+    }
+}
+
+/// Stores an entry in the credential manager, or in ample's [CredentialFile] when there's no credential manager to use
+/// or the file was asked for (see [file_chosen]).
+pub fn set_entry(entry_name: &str, value: &str) -> Result<(), keyring::Error> {
+    if file_chosen() {
+        return credentials_file()?.set(entry_name, value).map_err(file_error);
+    }
+
+    match Entry::new_with_target(entry_name, crate::APP_NAME, crate::APP_NAME).and_then(|entry| entry.set_password(value)) {
+        Err(err) if keyring_unavailable(&err) => {
+            info!("No credential manager to store {entry_name} in ({err}), storing it in the credentials file");
+            credentials_file()?.set(entry_name, value).map_err(file_error)
+        }
+        result => result,
+    }
+}
+
+/// Removes the LastFM username from the OS password/credential manager.
+/// Returns false if there was no username stored.
+pub fn forget_lastfm_username() -> Result<bool, keyring::Error> {
+    forget_entry(USERNAME_ENTRY_NAME)
+}
+
+/// Removes the LastFM password from the OS password/credential manager.
+/// Returns false if there was no password stored.
+pub fn forget_lastfm_password() -> Result<bool, keyring::Error> {
+    forget_entry(PASSWORD_ENTRY_NAME)
+}
+
+/// Removes the LastFM api secret from the OS password/credential manager.
+/// Returns false if there was no secret stored.
+pub fn forget_lastfm_secret() -> Result<bool, keyring::Error> {
+    forget_entry(SECRET_ENTRY_NAME)
+}
+
+/// Removes the LastFM api key from the OS password/credential manager.
+/// Returns false if there was no api key stored.
+pub fn forget_lastfm_api_key() -> Result<bool, keyring::Error> {
+    forget_entry(API_KEY_ENTRY_NAME)
+}
+
+/// Deletes the credential manager entry with the given name. A missing entry isn't an error
+/// since the end result is the same, but it is reported by returning false.
+pub fn forget_entry(entry_name: &str) -> Result<bool, keyring::Error> {
+    if file_chosen() {
+        return credentials_file()?.delete(entry_name).map_err(file_error);
+    }
+
+    match Entry::new_with_target(entry_name, crate::APP_NAME, crate::APP_NAME).and_then(|entry| entry.delete_credential()) {
+        Ok(()) => Ok(true),
+        Err(keyring::Error::NoEntry) => Ok(false),
+        Err(err) if keyring_unavailable(&err) => credentials_file()?.delete(entry_name).map_err(file_error),
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether every entry goes to the [CredentialFile] instead of the credential manager, which is when AMPLE_CREDENTIALS_FILE
+/// is "true" or AMPLE_CREDENTIALS_PASSPHRASE is set. Some credential managers work without anywhere to keep entries
+/// (like linux's kernel keyring, which forgets them on reboot), so it can't always be told from their errors.
+fn file_chosen() -> bool {
+    let asked_for = env::var("AMPLE_CREDENTIALS_FILE").is_ok_and(|value| value.trim().eq_ignore_ascii_case("true"));
+    let passphrase = env::var("AMPLE_CREDENTIALS_PASSPHRASE").is_ok_and(|passphrase| !passphrase.is_empty());
+
+    asked_for || passphrase
+}
+
+fn credentials_file() -> Result<CredentialFile, keyring::Error> {
+    CredentialFile::open_default().map_err(file_error)
+}
+
+/// Whether `err` means there's no credential manager at all, as opposed to the entry just not being there.
+fn keyring_unavailable(err: &keyring::Error) -> bool {
+    matches!(err, keyring::Error::NoStorageAccess(_) | keyring::Error::PlatformFailure(_))
+}
+
+/// Errors from the credentials file are reported the same way as the credential manager's.
+fn file_error(err: CredentialFileError) -> keyring::Error {
+    keyring::Error::PlatformFailure(Box::new(err))
+}
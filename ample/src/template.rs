@@ -0,0 +1,124 @@
+use std::str::FromStr;
+
+use sys_media::MediaInfo;
+use thiserror::Error;
+
+/// Text with placeholders that get filled in from what's playing: `{song}`, `{artist}`, `{album}` and `{player}`.
+/// Braces that should show up as-is are doubled, e.g. `{{` for `{`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Song,
+    Artist,
+    Album,
+    Player,
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum TemplateError {
+    #[error("Unknown placeholder {{{0}}}, expected one of: {{song}}, {{artist}}, {{album}}, {{player}}")]
+    UnknownPlaceholder(String),
+    #[error("Placeholder starting at index {0} is never closed")]
+    Unclosed(usize),
+    #[error("Unmatched '}}' at index {0}, use '}}}}' for a literal '}}'")]
+    Unmatched(usize),
+}
+
+impl FromStr for Template {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.char_indices().peekable();
+
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '{' if chars.next_if(|&(_, next)| next == '{').is_some() => text.push('{'),
+                '}' if chars.next_if(|&(_, next)| next == '}').is_some() => text.push('}'),
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some((_, '}')) => break,
+                            Some((_, c)) => name.push(c),
+                            None => return Err(TemplateError::Unclosed(index)),
+                        }
+                    }
+
+                    let field = match name.trim() {
+                        "song" => Field::Song,
+                        "artist" => Field::Artist,
+                        "album" => Field::Album,
+                        "player" => Field::Player,
+                        _ => return Err(TemplateError::UnknownPlaceholder(name)),
+                    };
+
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Field(field));
+                }
+                '}' => return Err(TemplateError::Unmatched(index)),
+                c => text.push(c),
+            }
+        }
+
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+
+        Ok(Template { parts })
+    }
+}
+
+impl Template {
+    pub fn render(&self, info: &MediaInfo) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => rendered.push_str(text),
+                Part::Field(Field::Song) => rendered.push_str(&info.song_name),
+                Part::Field(Field::Artist) => rendered.push_str(&info.artist_name),
+                Part::Field(Field::Album) => rendered.push_str(&info.album_name),
+                Part::Field(Field::Player) => rendered.push_str(&info.player_name),
+            }
+        }
+
+        rendered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::media;
+
+    #[test]
+    fn render_templates() {
+        let template: Template = "{artist} • {album}".parse().unwrap();
+        assert_eq!(template.render(&media()), "Catfish and the Bottlemen • The Balcony");
+
+        let template: Template = "{{{song}}} on { player }".parse().unwrap();
+        assert_eq!(template.render(&media()), "{7} on AppleMusic.exe");
+
+        assert_eq!("".parse::<Template>().unwrap().render(&media()), "");
+    }
+
+    #[test]
+    fn invalid_templates() {
+        assert_eq!("{title}".parse::<Template>(), Err(TemplateError::UnknownPlaceholder("title".to_owned())));
+        assert_eq!("by {artist".parse::<Template>(), Err(TemplateError::Unclosed(3)));
+        assert_eq!("song}".parse::<Template>(), Err(TemplateError::Unmatched(4)));
+    }
+}
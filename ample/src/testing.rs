@@ -0,0 +1,18 @@
+//! Helpers shared by the tests of every module.
+
+use sys_media::{MediaInfo, MediaStatus, MediaType};
+
+/// "7" playing in Apple Music from the start, with every name and the length (3:29) filled in.
+/// Tests change whatever fields they care about from there.
+pub fn media() -> MediaInfo {
+    MediaInfo {
+        song_name: "7".to_owned(),
+        artist_name: "Catfish and the Bottlemen".to_owned(),
+        album_name: "The Balcony".to_owned(),
+        player_name: "AppleMusic.exe".to_owned(),
+        end_time: 209_000_000,
+        status: MediaStatus::Playing,
+        media_type: MediaType::Music,
+        ..Default::default()
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use log::*;
+use sys_media::Thumbnail;
+
+// How long a client gets to send its request before it's dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the cover a player shared for the current track over HTTP, since Discord only shows images it can download.
+/// Only listens on the loopback address and only ever keeps the one image for what's playing right now.
+pub struct ThumbnailServer {
+    // Root the covers are linked from, the server's own address unless AMPLE_THUMBNAIL_URL says otherwise
+    url: String,
+    current: Arc<Mutex<Option<Thumbnail>>>,
+    // Goes up with every cover so that Discord doesn't keep showing the one it cached for the last track
+    version: u64,
+}
+
+impl ThumbnailServer {
+    /// Starts serving on `port` of 127.0.0.1. `public_url` is where Discord can reach the server instead,
+    /// for when it's forwarded from somewhere else.
+    pub fn start(port: u16, public_url: Option<&str>) -> io::Result<ThumbnailServer> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        let url = match public_url {
+            Some(url) => url.trim_end_matches('/').to_owned(),
+            None => format!("http://{}", listener.local_addr()?),
+        };
+
+        let current = Arc::new(Mutex::new(None));
+        let served = Arc::clone(&current);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| respond(stream, &served));
+                if let Err(err) = result {
+                    debug!("Failed to answer a cover request: {err}");
+                }
+            }
+        });
+
+        info!("Serving covers from players at {url}");
+        Ok(ThumbnailServer { url, current, version: 0 })
+    }
+
+    /// Serves `thumbnail` from now on and returns the url it can be found at.
+    pub fn serve(&mut self, thumbnail: Thumbnail) -> String {
+        *self.current.lock().unwrap() = Some(thumbnail);
+        self.version += 1;
+        format!("{}/cover?v={}", self.url, self.version)
+    }
+
+    /// Stops serving the last cover, for when the current player didn't share one.
+    pub fn clear(&self) {
+        *self.current.lock().unwrap() = None;
+    }
+}
+
+/// Answers a single request. Only `/cover` is there, with any query, and only while there's a cover to serve.
+fn respond(mut stream: TcpStream, current: &Mutex<Option<Thumbnail>>) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers don't matter, but closing the connection before reading them can reset it before the answer arrives
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or_default();
+    let is_cover = matches!(method, "GET" | "HEAD") && path.split('?').next() == Some("/cover");
+
+    let thumbnail = if is_cover { current.lock().unwrap().clone() } else { None };
+    match thumbnail {
+        Some(thumbnail) => {
+            // The type comes from the player, so make sure it can't add headers of its own
+            let content_type = if thumbnail.content_type.chars().any(char::is_control) {
+                "application/octet-stream"
+            } else {
+                &thumbnail.content_type
+            };
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                thumbnail.data.len()
+            )?;
+            if method == "GET" {
+                stream.write_all(&thumbnail.data)?;
+            }
+        }
+        None => stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")?,
+    }
+
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+
+    fn get(server: &ThumbnailServer, request: &str) -> Vec<u8> {
+        let mut stream = TcpStream::connect(server.url.trim_start_matches("http://")).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_current_cover() {
+        let mut server = ThumbnailServer::start(0, None).unwrap();
+        let request = "GET /cover?v=1 HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        assert!(get(&server, request).starts_with(b"HTTP/1.1 404"));
+
+        let url = server.serve(Thumbnail {
+            content_type: "image/png".to_owned(),
+            data: b"not really a png".to_vec(),
+        });
+        assert_eq!(url, format!("{}/cover?v=1", server.url));
+
+        let response = get(&server, request);
+        assert!(response.starts_with(b"HTTP/1.1 200 OK\r\nContent-Type: image/png\r\nContent-Length: 16\r\n"));
+        assert!(response.ends_with(b"\r\n\r\nnot really a png"));
+        assert!(get(&server, "GET /other HTTP/1.1\r\n\r\n").starts_with(b"HTTP/1.1 404"));
+
+        server.clear();
+        assert!(get(&server, request).starts_with(b"HTTP/1.1 404"));
+    }
+}
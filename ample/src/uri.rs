@@ -1,3 +1,5 @@
+use thiserror::Error;
+
 // NONALNUM here meaning non-alphanumeric
 const UNRESERVED_NONALNUM_CHARS: [char; 4] = ['-', '_', '~', '.'];
 
@@ -14,12 +16,9 @@ pub fn percent_encode(value: &str) -> String {
                 // formatting string should format the char as a hex number with 0's padding the beginning of the number
                 encoded_string.push_str(&format!("%{char32:02x}"));
             } else {
-                let mut char_bytes: [u8;4] = [0;4];
-                c.encode_utf8(&mut char_bytes);
-                for b in char_bytes {
-                    if b == 0 {
-                        continue;
-                    }
+                // encode_utf8 returns only the bytes the char actually needs
+                let mut char_bytes = [0; 4];
+                for b in c.encode_utf8(&mut char_bytes).as_bytes() {
                     encoded_string.push_str(&format!("%{b:02x}"));
                 }
             }
@@ -31,6 +30,80 @@ pub fn percent_encode(value: &str) -> String {
     encoded_string
 }
 
+/// Builds a query string out of key/value pairs.
+///
+/// Pairs are always written sorted by key (then value) no matter what order they were added in,
+/// so anything that needs to agree on the order of parameters, like an API signature,
+/// can use [QueryString::sorted_params] and get the same order the string is written in.
+#[derive(Debug, Default, Clone)]
+pub struct QueryString<'a> {
+    params: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> QueryString<'a> {
+    pub fn new() -> QueryString<'a> {
+        QueryString::default()
+    }
+
+    pub fn param(mut self, key: &'a str, value: &'a str) -> QueryString<'a> {
+        self.params.push((key, value));
+        self
+    }
+
+    /// The unencoded pairs in the order they will be written.
+    pub fn sorted_params(&self) -> Vec<(&'a str, &'a str)> {
+        let mut params = self.params.clone();
+        params.sort();
+        params
+    }
+
+    /// Joins the percent-encoded pairs with '&'. Does not include a leading '?'.
+    pub fn build(&self) -> String {
+        self.sorted_params()
+            .into_iter()
+            .map(|(key, value)| format!("{}={}", percent_encode(key), percent_encode(value)))
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum DecodeError {
+    #[error("Incomplete percent-encoded sequence at index {0}")]
+    Incomplete(usize),
+    #[error("Invalid hex digits in percent-encoded sequence at index {0}")]
+    InvalidHex(usize),
+    #[error("Decoded value is not valid UTF-8: {0}")]
+    InvalidUtf8(#[from] std::string::FromUtf8Error),
+}
+
+/// The inverse of [percent_encode]. Multi-byte characters are reassembled from their
+/// encoded bytes, so the result has to be valid UTF-8.
+pub fn percent_decode(value: &str) -> Result<String, DecodeError> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes.get(i + 1..i + 3).ok_or(DecodeError::Incomplete(i))?;
+            // from_str_radix allows a leading '+' so check the digits ourselves
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return Err(DecodeError::InvalidHex(i));
+            }
+
+            let hex = std::str::from_utf8(hex).expect("hex digits should be ascii");
+            decoded.push(u8::from_str_radix(hex, 16).expect("hex digits should be valid"));
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Ok(String::from_utf8(decoded)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -41,6 +114,56 @@ mod tests {
         assert_eq!(percent_encode("hello world"), "hello%20world");
         assert_eq!(percent_encode("ABC123"), "ABC123");
         assert_eq!(percent_encode("King Gizzard and the Lizard Wizard"), "King%20Gizzard%20and%20the%20Lizard%20Wizard");
-        assert_eq!(percent_encode("€"), "%e2%82%ac")
+        assert_eq!(percent_encode("€"), "%e2%82%ac");
+        assert_eq!(percent_encode("é"), "%c3%a9");
+        assert_eq!(percent_encode("😀"), "%f0%9f%98%80");
+    }
+
+    #[test]
+    fn percent_decoding() {
+        assert_eq!(percent_decode("hello%20world"), Ok("hello world".to_owned()));
+        assert_eq!(percent_decode("%E2%82%AC"), Ok("€".to_owned()));
+        assert_eq!(percent_decode("no-encoding"), Ok("no-encoding".to_owned()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn percent_round_trip() {
+        let cases = [
+            "!#$&\'()*+,/:;=?@[]",
+            "hello world",
+            "ABC123",
+            "King Gizzard and the Lizard Wizard",
+            "€",
+            "é",
+            "😀",
+        ];
+
+        for case in cases {
+            assert_eq!(percent_decode(&percent_encode(case)), Ok(case.to_owned()));
+        }
+    }
+
+    #[test]
+    fn malformed_percent_decoding() {
+        assert_eq!(percent_decode("100%"), Err(DecodeError::Incomplete(3)));
+        assert_eq!(percent_decode("%2"), Err(DecodeError::Incomplete(0)));
+        assert_eq!(percent_decode("%zz"), Err(DecodeError::InvalidHex(0)));
+        assert_eq!(percent_decode("%+f"), Err(DecodeError::InvalidHex(0)));
+        assert!(matches!(percent_decode("%e2%82"), Err(DecodeError::InvalidUtf8(_))));
+    }
+
+    #[test]
+    fn query_string() {
+        let query = QueryString::new()
+            .param("track", "Hey Jude")
+            .param("artist", "The Beatles")
+            .param("api_key", "€");
+
+        assert_eq!(query.build(), "api_key=%e2%82%ac&artist=The%20Beatles&track=Hey%20Jude");
+        assert_eq!(
+            query.sorted_params(),
+            vec![("api_key", "€"), ("artist", "The Beatles"), ("track", "Hey Jude")]
+        );
+        assert_eq!(QueryString::new().build(), "");
+    }
+}
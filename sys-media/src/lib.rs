@@ -1,13 +1,31 @@
 use core::fmt;
+use std::{
+    error::Error,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
+#[cfg(all(windows, feature = "windows"))]
+use std::sync::{Arc, Mutex};
+
+#[cfg(all(windows, feature = "windows"))]
 use windows::Media::Control::GlobalSystemMediaTransportControlsSessionManager;
 
 pub mod consts;
+#[cfg(all(windows, feature = "windows"))]
 mod win_media;
 
 /// An object containing info about whatever is currently playing. This info is set by
 /// external programs and thus may be formatted differently from each other and some info may be absent.
-#[derive(Debug, Clone)]
+/// It's kept exactly as the OS reports it, see [normalize_apple_music] for cleaning up after Apple Music.
+///
+/// The default is stopped media of an unknown type with every name empty, which is mostly useful for filling in
+/// the fields a test doesn't care about: `MediaInfo { song_name: "7".to_owned(), ..Default::default() }`.
+///
+/// With the "serde" feature it can be (de)serialized with the field names as they are here, and fields missing
+/// when deserializing get their default.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(default))]
 pub struct MediaInfo {
     /// Name of the app or executable that started playing this media
     pub player_name: String,
@@ -16,13 +34,56 @@ pub struct MediaInfo {
     pub album_name: String,
     pub status: MediaStatus,
     pub media_type: MediaType,
-    /// Length of media in microseconds
-    // This would be in milliseconds but for some reason Windows has it in
-    // a length of time that doesn't have an actual name (it's like 10x smaller than a microsecond),
-    // but the last few digits aren't significant anyway
+    /// Length of media in microseconds, 0 if the player doesn't know. See [MediaInfo::length]
     pub end_time: i64,
-    /// Amount of time having watched / listened to media in microseconds
+    /// Amount of time having watched / listened to media in microseconds. See [MediaInfo::position]
     pub current_position: i64,
+    /// None if the player doesn't support shuffling
+    pub shuffle: Option<bool>,
+    /// None if the player doesn't support repeating
+    pub repeat: Option<RepeatMode>,
+    /// Position of the track on its album, None if the player doesn't report it
+    pub track_number: Option<u32>,
+    /// Number of tracks on the album, None if the player doesn't report it
+    pub album_track_count: Option<u32>,
+}
+
+/// The cover image a player shared for what it's playing, e.g. the art embedded in a local file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+    /// MIME type of the image as the player reported it, e.g. "image/png"
+    pub content_type: String,
+    pub data: Vec<u8>,
+}
+
+impl MediaInfo {
+    /// Length of the media, None if the player didn't report one.
+    pub fn length(&self) -> Option<Duration> {
+        Some(micros_to_duration(self.end_time)).filter(|length| !length.is_zero())
+    }
+
+    /// How far into the media the player is.
+    pub fn position(&self) -> Duration {
+        micros_to_duration(self.current_position)
+    }
+
+    /// Whether the title or the artist is blank, which some players report for a moment while switching tracks.
+    pub fn is_incomplete(&self) -> bool {
+        self.song_name.trim().is_empty() || self.artist_name.trim().is_empty()
+    }
+
+    /// Whether `other` is the same track as this one. Unlike `==`, a name that's empty in only one of them isn't
+    /// counted as a difference, since players (like Apple Music) can briefly leave names out while switching tracks.
+    pub fn is_same_track(&self, other: &MediaInfo) -> bool {
+        fn same_name(name: &str, other: &str) -> bool {
+            name == other || name.is_empty() || other.is_empty()
+        }
+
+        self.player_name == other.player_name
+            && same_name(&self.song_name, &other.song_name)
+            && same_name(&self.artist_name, &other.artist_name)
+            && same_name(&self.album_name, &other.album_name)
+    }
 }
 
 impl PartialEq for MediaInfo {
@@ -34,82 +95,333 @@ impl PartialEq for MediaInfo {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Times from players should never be negative, but they're reported as signed numbers anyway.
+fn micros_to_duration(micros: i64) -> Duration {
+    Duration::from_micros(micros.max(0) as u64)
+}
+
+/// WinRT's `TimeSpan`, which the media session's times come in, counts ticks of 100 nanoseconds. There's 10 to a microsecond.
+/// Public so that code further down can check its own handling of [MediaInfo]'s times against what Windows reports.
+pub fn win_ticks_to_micros(ticks: i64) -> i64 {
+    ticks / 10
+}
+
+/// WinRT's `DateTime` counts the same ticks as a `TimeSpan`, from the start of 1601 instead of 1970.
+const WIN_EPOCH_TO_UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+
+/// How long before `now` the WinRT `DateTime` `ticks` was. Times after `now`, from a clock that was turned back,
+/// and unset times count as no time at all.
+#[cfg_attr(not(all(windows, feature = "windows")), allow(dead_code))]
+fn win_time_since(ticks: i64, now: SystemTime) -> Duration {
+    if ticks <= 0 {
+        return Duration::ZERO;
+    }
+
+    let since_unix_epoch = micros_to_duration(win_ticks_to_micros(ticks - WIN_EPOCH_TO_UNIX_EPOCH_TICKS));
+    now.duration_since(UNIX_EPOCH + since_unix_epoch).unwrap_or_default()
+}
+
+/// Where a playing track is `elapsed` after the player reported `position`, in microseconds.
+/// Players only report a new position when something changes (Apple Music does on seeks, pauses and new tracks),
+/// so the reported one is only right at the moment it was reported. Stops at `end_time` if the length is known.
+#[cfg_attr(not(all(windows, feature = "windows")), allow(dead_code))]
+fn advance_position(position: i64, end_time: i64, elapsed: Duration) -> i64 {
+    let advanced = position.saturating_add(elapsed.as_micros().try_into().unwrap_or(i64::MAX));
+    if end_time > 0 {
+        advanced.min(end_time.max(position))
+    } else {
+        advanced
+    }
+}
+
+/// Apple Music reports the artist and album together as the artist name ("Artist — Album") and leaves the album empty.
+/// This splits them back up. Does nothing for other players, or if the artist name doesn't have exactly one dash in it.
+pub fn normalize_apple_music(info: &mut MediaInfo) {
+    if info.player_name != consts::APPLE_MUSIC_ID {
+        return;
+    }
+
+    if let (artist, Some(album)) = parse_apple_music_artist_album(&info.artist_name) {
+        info.artist_name = artist;
+        info.album_name = album;
+    }
+}
+
+/// Splits Apple Music's "Artist — Album" into the artist and album, trimming both. The album is `None` when there's no
+/// album in `raw`, in which case the artist is all of it.
+///
+/// The dash is not a normal '-', its actually '—', which I didn't know was a different character. Neat.
+/// Apple Music always puts spaces around it, so a dash without spaces is part of a name. With more than one there's
+/// no telling whether the artist or the album has the extra dash, so all of it is kept as the artist rather than guessing.
+pub fn parse_apple_music_artist_album(raw: &str) -> (String, Option<String>) {
+    let mut parts = raw.split(" — ");
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some(artist), Some(album), None) if !album.trim().is_empty() => (artist.trim().to_owned(), Some(album.trim().to_owned())),
+        (Some(artist), Some(_), None) => (artist.trim().to_owned(), None),
+        _ => (raw.trim().to_owned(), None),
+    }
+}
+
+/// Serialized with the same lowercase names as its Display impl, as are [RepeatMode] and [MediaType].
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
 pub enum MediaStatus {
     Closed,
     Opened,
     Changing,
+    #[default]
     Stopped,
     Playing,
     Paused,
 }
 
-#[derive(Debug, Clone)]
+impl fmt::Display for MediaStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MediaStatus::Closed => "closed",
+            MediaStatus::Opened => "opened",
+            MediaStatus::Changing => "changing",
+            MediaStatus::Stopped => "stopped",
+            MediaStatus::Playing => "playing",
+            MediaStatus::Paused => "paused",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// What the player repeats once the current media finishes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
+pub enum RepeatMode {
+    None,
+    Track,
+    List,
+}
+
+impl fmt::Display for RepeatMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RepeatMode::None => "none",
+            RepeatMode::Track => "track",
+            RepeatMode::List => "list",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "lowercase"))]
 pub enum MediaType {
+    #[default]
     Unknown,
     Music,
     Video,
     Image,
 }
 
-#[derive(Debug)]
-pub enum MediaError {
-    Windows(windows::core::Error),
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MediaType::Unknown => "unknown",
+            MediaType::Music => "music",
+            MediaType::Video => "video",
+            MediaType::Image => "image",
+        };
+
+        write!(f, "{name}")
+    }
 }
 
-impl MediaError {
-    pub fn is_false_error(&self) -> bool {
-        // this should eventually be refutable when other variants are added
-        #[allow(irrefutable_let_patterns)]
-        if let MediaError::Windows(win_err) = self {
-            // NOTE: rust-analyzer thinks this is an error for some reason?
-            win_err.code() == windows_result::HRESULT(0)
-        } else {
-            false
+/// Parses the names written by `MediaType`'s Display impl, ignoring case.
+impl FromStr for MediaType {
+    type Err = ParseMediaTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "unknown" => Ok(MediaType::Unknown),
+            "music" => Ok(MediaType::Music),
+            "video" => Ok(MediaType::Video),
+            "image" => Ok(MediaType::Image),
+            _ => Err(ParseMediaTypeError(s.to_owned())),
         }
     }
 }
 
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseMediaTypeError(String);
+
+impl fmt::Display for ParseMediaTypeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown media type {:?}, expected one of: unknown, music, video, image", self.0)
+    }
+}
+
+impl Error for ParseMediaTypeError {}
+
+#[derive(Debug)]
+pub enum MediaError {
+    #[cfg(all(windows, feature = "windows"))]
+    Windows(windows::core::Error),
+    /// There's no way to get media info on this platform, or it wasn't compiled in
+    Unsupported,
+}
+
+#[cfg(all(windows, feature = "windows"))]
 impl From<windows::core::Error> for MediaError {
     fn from(value: windows::core::Error) -> Self {
         MediaError::Windows(value)
     }
 }
 
+impl MediaError {
+    /// The HRESULT of a Windows error, e.g. `0x80010108` (RPC_E_DISCONNECTED) as an i32.
+    /// Worth including when reporting a problem, since Windows' messages for them can be vague.
+    pub fn hresult(&self) -> Option<i32> {
+        match self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaError::Windows(error) => Some(error.code().0),
+            MediaError::Unsupported => None,
+        }
+    }
+}
+
 impl fmt::Display for MediaError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            MediaError::Windows(error) => write!(f, "An error occurred while trying to get currently playing media: {error}"),
+            #[cfg(all(windows, feature = "windows"))]
+            MediaError::Windows(error) => write!(
+                f,
+                "An error occurred while trying to get currently playing media: {error} (HRESULT {:#010X})",
+                error.code().0 as u32
+            ),
+            MediaError::Unsupported => write!(f, "Getting currently playing media isn't supported on this platform"),
         }
     }
 }
 
 /// An object capable of getting information about the currently playing media (Music, Video, etc.).
+/// Only has the variants for backends compiled in for the current platform.
 pub enum MediaListener {
+    #[cfg(all(windows, feature = "windows"))]
     Windows {
-        session_manager: GlobalSystemMediaTransportControlsSessionManager,
+        /// Acquired in `get_listener` and reused for every call. Requesting a new manager leaks memory
+        /// (see `win_media::get_session_manager`), so only `reconnect` requests another one.
+        session_manager: Mutex<GlobalSystemMediaTransportControlsSessionManager>,
+        /// Passed to `on_change`, kept so that it can be registered with the new manager after reconnecting
+        on_change: Mutex<Option<Arc<dyn Fn() + Send + Sync>>>,
     },
 }
 
 impl MediaListener {
     /// Get the currently playing song's info including what app started playing it.
     /// Blocks execution if waiting on async or syscalls.
+    ///
+    /// The current session is looked up again on every call, so restarting or switching players is picked up.
+    ///
+    /// Returns `Ok(None)` when there is no media session, i.e. no player that reports what it's playing is open.
+    /// A paused or stopped player still has a session and returns its info.
     pub fn get_current_playing_info(&self) -> Result<Option<MediaInfo>, MediaError> {
-        match self {
-            MediaListener::Windows { session_manager } => {
-                let session = win_media::get_current_session(session_manager)?;
+        // Dereferenced so that the match is still exhaustive when no backend is compiled in
+        match *self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaListener::Windows { ref session_manager, .. } => {
+                let session_manager = session_manager.lock().unwrap().clone();
+                // Don't hold on to the session, it goes stale as soon as its player closes
+                let Some(session) = win_media::get_current_session(&session_manager)? else {
+                    return Ok(None);
+                };
                 win_media::get_current_session_info(&session).map_err(|err| err.into())
             }
         }
     }
+
+    /// Get the cover image the current player shared for what it's playing. Kept out of [MediaListener::get_current_playing_info]
+    /// since reading the image is slow enough that it should only be done once per track.
+    ///
+    /// Returns `Ok(None)` when there is no media session or the player didn't share a cover.
+    pub fn get_thumbnail(&self) -> Result<Option<Thumbnail>, MediaError> {
+        match *self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaListener::Windows { ref session_manager, .. } => {
+                let session_manager = session_manager.lock().unwrap().clone();
+                let Some(session) = win_media::get_current_session(&session_manager)? else {
+                    return Ok(None);
+                };
+                win_media::get_session_thumbnail(&session).map_err(|err| err.into())
+            }
+        }
+    }
+
+    /// Calls `on_change` as soon as what's playing might have changed, like when the song changes or gets paused.
+    /// `on_change` is called from other threads, so it should only wake up whatever calls `get_current_playing_info`.
+    ///
+    /// Some players don't send every change, so this doesn't replace checking every now and then.
+    #[cfg_attr(not(all(windows, feature = "windows")), allow(unused_variables))]
+    pub fn on_change(&self, on_change: impl Fn() + Send + Sync + 'static) -> Result<(), MediaError> {
+        match *self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaListener::Windows {
+                ref session_manager,
+                on_change: ref watching,
+            } => {
+                let on_change: Arc<dyn Fn() + Send + Sync> = Arc::new(on_change);
+                win_media::watch_current_session(&session_manager.lock().unwrap(), on_change.clone())?;
+                *watching.lock().unwrap() = Some(on_change);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the OS media controls answer at all right now, regardless of whether anything is playing.
+    /// Lets callers tell "media detection doesn't work here" apart from the errors of a single failed fetch.
+    pub fn is_available(&self) -> bool {
+        match *self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaListener::Windows { ref session_manager, .. } => win_media::is_available(&session_manager.lock().unwrap()),
+        }
+    }
+
+    /// Starts over with a new connection to the OS, for when getting media keeps failing.
+    /// Anything passed to `on_change` keeps being called.
+    ///
+    /// On Windows this requests a new session manager, which leaks memory (see [get_listener]),
+    /// so this should only be done every now and then while things are broken.
+    pub fn reconnect(&self) -> Result<(), MediaError> {
+        match *self {
+            #[cfg(all(windows, feature = "windows"))]
+            MediaListener::Windows {
+                ref session_manager,
+                ref on_change,
+            } => {
+                let new_manager = win_media::get_session_manager()?;
+                if let Some(ref on_change) = *on_change.lock().unwrap() {
+                    win_media::watch_current_session(&new_manager, on_change.clone())?;
+                }
+
+                *session_manager.lock().unwrap() = new_manager;
+                Ok(())
+            }
+        }
+    }
 }
 
-/// Creates a MediaListener for the given OS
+/// Creates a MediaListener for the given OS.
+///
+/// On Windows this requests the session manager, which leaks memory every time it's done.
+/// Create one listener and keep it around for the lifetime of the program.
 pub fn get_listener() -> Result<MediaListener, MediaError> {
-    if cfg!(windows) {
+    #[cfg(all(windows, feature = "windows"))]
+    {
         let session_manager = win_media::get_session_manager()?;
-        Ok(MediaListener::Windows { session_manager })
-    } else {
+        Ok(MediaListener::Windows {
+            session_manager: Mutex::new(session_manager),
+            on_change: Mutex::new(None),
+        })
+    }
+    #[cfg(not(all(windows, feature = "windows")))]
+    {
         // Possible ways I've found to get info on linux:
         // - playerctl
         // This could be done the "dirty" way by using processes and piping that info inside the library.
@@ -127,6 +439,164 @@ pub fn get_listener() -> Result<MediaListener, MediaError> {
         // - from scratch?
         // If there is a nice way to "ask" the OS about info from the current media player, we might be able to sidestep any gtk / GLib stuff.
         // However, I fear this is actually not simple to do.
-        todo!()
+        Err(MediaError::Unsupported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn media(song_name: &str, artist_name: &str) -> MediaInfo {
+        MediaInfo {
+            player_name: "AppleMusic.exe".to_owned(),
+            artist_name: artist_name.to_owned(),
+            song_name: song_name.to_owned(),
+            album_name: "The Balcony".to_owned(),
+            status: MediaStatus::Playing,
+            media_type: MediaType::Music,
+            end_time: 209_000_000,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn same_track() {
+        let playing = media("7", "Catfish and the Bottlemen");
+
+        assert!(playing.is_same_track(&playing));
+        assert!(playing.is_same_track(&media("", "Catfish and the Bottlemen")));
+        assert!(media("7", "").is_same_track(&playing));
+        assert!(!playing.is_same_track(&media("Kathleen", "Catfish and the Bottlemen")));
+
+        let mut other_player = playing.clone();
+        other_player.player_name = "Spotify.exe".to_owned();
+        assert!(!playing.is_same_track(&other_player));
+    }
+
+    #[test]
+    fn apple_music_names() {
+        let mut info = media("7", "Catfish and the Bottlemen — The Balcony");
+        info.album_name = String::new();
+        normalize_apple_music(&mut info);
+        assert_eq!(info.artist_name, "Catfish and the Bottlemen");
+        assert_eq!(info.album_name, "The Balcony");
+
+        // Already split
+        let mut info = media("7", "Catfish and the Bottlemen");
+        normalize_apple_music(&mut info);
+        assert_eq!(info.artist_name, "Catfish and the Bottlemen");
+        assert_eq!(info.album_name, "The Balcony");
+
+        let mut info = media("Clair de Lune", "Debussy — Suite bergamasque");
+        info.player_name = "Spotify.exe".to_owned();
+        normalize_apple_music(&mut info);
+        assert_eq!(info.artist_name, "Debussy — Suite bergamasque");
+    }
+
+    #[test]
+    fn apple_music_artist_album() {
+        let parse = parse_apple_music_artist_album;
+        let split = |artist: &str, album: Option<&str>| (artist.to_owned(), album.map(str::to_owned));
+
+        assert_eq!(
+            parse("Catfish and the Bottlemen — The Balcony"),
+            split("Catfish and the Bottlemen", Some("The Balcony"))
+        );
+        assert_eq!(parse("Catfish and the Bottlemen"), split("Catfish and the Bottlemen", None));
+        assert_eq!(parse("  Debussy — Suite bergamasque  "), split("Debussy", Some("Suite bergamasque")));
+        assert_eq!(
+            parse("Debussy — Suite bergamasque — Live"),
+            split("Debussy — Suite bergamasque — Live", None)
+        );
+        assert_eq!(parse("Debussy — Suite bergamasque — "), split("Debussy — Suite bergamasque —", None));
+        assert_eq!(
+            parse("Sigur Rós—Jónsi — Riceboy Sleeps"),
+            split("Sigur Rós—Jónsi", Some("Riceboy Sleeps"))
+        );
+        assert_eq!(parse("Sigur Rós—Jónsi"), split("Sigur Rós—Jónsi", None));
+        assert_eq!(parse("Catfish and the Bottlemen — "), split("Catfish and the Bottlemen", None));
+        assert_eq!(parse(""), split("", None));
+    }
+
+    #[test]
+    fn media_times() {
+        // 3:29 in 100 nanosecond ticks
+        assert_eq!(win_ticks_to_micros(2_090_000_000), 209_000_000);
+        assert_eq!(win_ticks_to_micros(15), 1);
+
+        let info = media("7", "Catfish and the Bottlemen");
+        assert_eq!(info.length(), Some(Duration::from_secs(209)));
+        assert_eq!(info.position(), Duration::ZERO);
+
+        let unknown = MediaInfo {
+            end_time: 0,
+            current_position: -5,
+            ..info
+        };
+        assert_eq!(unknown.length(), None);
+        assert_eq!(unknown.position(), Duration::ZERO);
+    }
+
+    #[test]
+    fn incomplete_media() {
+        assert!(!media("7", "Catfish and the Bottlemen").is_incomplete());
+        assert!(media(" ", "Catfish and the Bottlemen").is_incomplete());
+        assert!(media("7", "").is_incomplete());
+    }
+
+    #[test]
+    fn advanced_positions() {
+        // 2023-11-14 22:13:20 UTC, as a WinRT DateTime
+        let updated = 1_700_000_000 * 10_000_000 + WIN_EPOCH_TO_UNIX_EPOCH_TICKS;
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_007);
+        assert_eq!(win_time_since(updated, now), Duration::from_secs(7));
+        assert_eq!(win_time_since(updated, now - Duration::from_secs(10)), Duration::ZERO);
+        assert_eq!(win_time_since(0, now), Duration::ZERO);
+
+        assert_eq!(advance_position(30_000_000, 209_000_000, Duration::from_secs(7)), 37_000_000);
+        assert_eq!(advance_position(205_000_000, 209_000_000, Duration::from_secs(7)), 209_000_000);
+        assert_eq!(advance_position(30_000_000, 0, Duration::from_secs(7)), 37_000_000);
+    }
+
+    #[test]
+    fn unsupported_error() {
+        assert_eq!(MediaError::Unsupported.hresult(), None);
+    }
+
+    #[test]
+    fn media_type_names() {
+        for media_type in [MediaType::Unknown, MediaType::Music, MediaType::Video, MediaType::Image] {
+            assert_eq!(media_type.to_string().parse(), Ok(media_type));
+        }
+
+        assert_eq!(" Music".parse(), Ok(MediaType::Music));
+        assert!("podcast".parse::<MediaType>().is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let info = MediaInfo {
+            current_position: 30_000_000,
+            shuffle: Some(false),
+            repeat: Some(RepeatMode::Track),
+            track_number: Some(1),
+            album_track_count: Some(12),
+            ..media("7", "Catfish and the Bottlemen")
+        };
+        let json = r#"{"player_name":"AppleMusic.exe","artist_name":"Catfish and the Bottlemen","song_name":"7","album_name":"The Balcony","status":"playing","media_type":"music","end_time":209000000,"current_position":30000000,"shuffle":false,"repeat":"track","track_number":1,"album_track_count":12}"#;
+
+        assert_eq!(serde_json::to_string(&info).unwrap(), json);
+        let parsed: MediaInfo = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+
+        // Missing fields get their default
+        let parsed: MediaInfo = serde_json::from_str(r#"{"song_name":"7","status":"paused","repeat":"list"}"#).unwrap();
+        assert_eq!(parsed.song_name, "7");
+        assert!(matches!(parsed.status, MediaStatus::Paused));
+        assert_eq!(parsed.media_type, MediaType::Unknown);
+        assert_eq!(parsed.repeat, Some(RepeatMode::List));
+        assert_eq!(parsed.shuffle, None);
     }
 }
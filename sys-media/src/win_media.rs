@@ -1,6 +1,22 @@
-use ::windows::Media::Control::{GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager};
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
-use crate::{consts::APPLE_MUSIC_ID, MediaInfo, MediaStatus, MediaType};
+use ::windows::{
+    core::Ref,
+    Foundation::TypedEventHandler,
+    Media::Control::{
+        CurrentSessionChangedEventArgs, GlobalSystemMediaTransportControlsSession, GlobalSystemMediaTransportControlsSessionManager,
+        MediaPropertiesChangedEventArgs, PlaybackInfoChangedEventArgs,
+    },
+    Storage::Streams::DataReader,
+};
+
+use crate::{advance_position, win_ticks_to_micros, win_time_since, MediaInfo, MediaStatus, MediaType, RepeatMode, Thumbnail};
+
+/// Covers bigger than this are left out rather than read into memory, no player should need more
+const MAX_THUMBNAIL_SIZE: u64 = 16 * 1024 * 1024;
 
 /// Gets a "SessionManager" from the Windows API.
 ///
@@ -8,6 +24,7 @@ use crate::{consts::APPLE_MUSIC_ID, MediaInfo, MediaStatus, MediaType};
 /// See here for more info: https://github.com/microsoft/windows-rs/issues/2061
 ///
 /// Limit the amount of times this function is called; preferably only once.
+/// `get_listener` gets the manager that's stored in the `MediaListener` for reuse, only `MediaListener::reconnect` requests another.
 /// This function blocks until the manager is received.
 pub fn get_session_manager() -> windows_result::Result<GlobalSystemMediaTransportControlsSessionManager> {
     let media_controller = GlobalSystemMediaTransportControlsSessionManager::RequestAsync()?;
@@ -16,10 +33,90 @@ pub fn get_session_manager() -> windows_result::Result<GlobalSystemMediaTranspor
 
 /// Gets the current "Session" from the SessionManager.
 /// This will usually get the session that is currently active (i.e. playing music) at the point of the function call.
+///
+/// Returns `Ok(None)` when there is no session at all, which is the case when no player that reports its media is open.
 pub fn get_current_session(
     session_manager: &GlobalSystemMediaTransportControlsSessionManager,
-) -> windows_result::Result<GlobalSystemMediaTransportControlsSession> {
-    session_manager.GetCurrentSession()
+) -> windows_result::Result<Option<GlobalSystemMediaTransportControlsSession>> {
+    match session_manager.GetCurrentSession() {
+        Ok(session) => Ok(Some(session)),
+        // Windows returns a null session without failing, which the bindings turn into an error with a success code
+        Err(err) if err.code().is_ok() => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Calls `on_change` whenever the current session changes, or the current session's media or playback info changes.
+/// Windows calls `on_change` from its own threads.
+pub fn watch_current_session(
+    session_manager: &GlobalSystemMediaTransportControlsSessionManager,
+    on_change: Arc<dyn Fn() + Send + Sync>,
+) -> windows_result::Result<()> {
+    let watched = Mutex::new(None);
+    watch_session(&watched, session_manager, &on_change)?;
+
+    let manager = session_manager.clone();
+    session_manager.CurrentSessionChanged(&TypedEventHandler::new(
+        move |_: Ref<GlobalSystemMediaTransportControlsSessionManager>, _: Ref<CurrentSessionChangedEventArgs>| {
+            watch_session(&watched, &manager, &on_change)?;
+            on_change();
+            Ok(())
+        },
+    ))?;
+
+    Ok(())
+}
+
+/// The session whose events are currently handled, kept so its handlers can be removed once it's no longer current.
+struct WatchedSession {
+    session: GlobalSystemMediaTransportControlsSession,
+    media_token: i64,
+    playback_token: i64,
+}
+
+fn watch_session(
+    watched: &Mutex<Option<WatchedSession>>,
+    session_manager: &GlobalSystemMediaTransportControlsSessionManager,
+    on_change: &Arc<dyn Fn() + Send + Sync>,
+) -> windows_result::Result<()> {
+    let mut watched = watched.lock().unwrap();
+    if let Some(old) = watched.take() {
+        // These fail if the old session's player already closed, in which case there's nothing to remove
+        let _ = old.session.RemoveMediaPropertiesChanged(old.media_token);
+        let _ = old.session.RemovePlaybackInfoChanged(old.playback_token);
+    }
+
+    // There's no current session while nothing is open, the next session change picks one up
+    let Ok(Some(session)) = get_current_session(session_manager) else {
+        return Ok(());
+    };
+
+    let media_change = on_change.clone();
+    let media_token = session.MediaPropertiesChanged(&TypedEventHandler::new(
+        move |_: Ref<GlobalSystemMediaTransportControlsSession>, _: Ref<MediaPropertiesChangedEventArgs>| {
+            media_change();
+            Ok(())
+        },
+    ))?;
+    let playback_change = on_change.clone();
+    let playback_token = session.PlaybackInfoChanged(&TypedEventHandler::new(
+        move |_: Ref<GlobalSystemMediaTransportControlsSession>, _: Ref<PlaybackInfoChangedEventArgs>| {
+            playback_change();
+            Ok(())
+        },
+    ))?;
+
+    *watched = Some(WatchedSession {
+        session,
+        media_token,
+        playback_token,
+    });
+    Ok(())
+}
+
+/// Whether the manager can still list the media sessions, which works with or without any players open.
+pub fn is_available(session_manager: &GlobalSystemMediaTransportControlsSessionManager) -> bool {
+    session_manager.GetSessions().is_ok()
 }
 
 /// Gets the relevant info about the currently active media from a session.
@@ -30,31 +127,29 @@ pub fn get_current_session_info(session: &GlobalSystemMediaTransportControlsSess
     let status: MediaStatus = get_raw_status_code(session)?.into();
     let m_type: MediaType = get_raw_media_type(session)?.into();
 
-    let mut artist_name = media_props.Artist()?.to_string_lossy();
-    let mut album_name = media_props.AlbumTitle()?.to_string_lossy();
-
-    // Apple Music combines the Artist and Album names together with a dash,
-    // however this dash is not a normal '-', its actually '—', which I didn't know was a different character.
-    // Neat.
-    if player.to_string_lossy() == APPLE_MUSIC_ID {
-        let apple_artist_album_string = media_props.Artist()?.to_string_lossy();
-        let mut splits = apple_artist_album_string.split('—');
-
-        artist_name = splits
-            .next()
-            .expect("apple music has changed how they display artist and album names")
-            .trim()
-            .to_owned();
-        album_name = splits
-            .next()
-            .expect("apple music has changed how they display artist and album names")
-            .trim()
-            .to_owned();
-    }
+    let artist_name = media_props.Artist()?.to_string_lossy();
+    let album_name = media_props.AlbumTitle()?.to_string_lossy();
+
+    // Players that don't know the track number report 0
+    let track_number = media_props.TrackNumber().ok().and_then(positive);
+    let album_track_count = media_props.AlbumTrackCount().ok().and_then(positive);
 
     let timeline_info = session.GetTimelineProperties()?;
-    let end_time = timeline_info.EndTime()?.Duration / 10; // For some reason, these values are 10x smaller than a microsecond?
-    let position = timeline_info.Position()?.Duration / 10;
+    let end_time = win_ticks_to_micros(timeline_info.EndTime()?.Duration);
+    let mut position = win_ticks_to_micros(timeline_info.Position()?.Duration);
+    if matches!(status, MediaStatus::Playing) {
+        let since_update = win_time_since(timeline_info.LastUpdatedTime()?.UniversalTime, SystemTime::now());
+        position = advance_position(position, end_time, since_update);
+    }
+
+    // Not every player supports shuffling or repeating, so these are left empty instead of failing the whole fetch
+    let playback_info = session.GetPlaybackInfo()?;
+    let shuffle = playback_info.IsShuffleActive().and_then(|shuffle| shuffle.Value()).ok();
+    let repeat = playback_info
+        .AutoRepeatMode()
+        .and_then(|repeat| repeat.Value())
+        .ok()
+        .map(|repeat| RawRepeatModeNumber(repeat.0).into());
 
     Ok(Some(MediaInfo {
         player_name: player.to_string_lossy(),
@@ -65,13 +160,49 @@ pub fn get_current_session_info(session: &GlobalSystemMediaTransportControlsSess
         media_type: m_type,
         end_time,
         current_position: position,
+        shuffle,
+        repeat,
+        track_number,
+        album_track_count,
+    }))
+}
+
+/// Reads the cover the session's player shared for its current media.
+pub fn get_session_thumbnail(session: &GlobalSystemMediaTransportControlsSession) -> windows_result::Result<Option<Thumbnail>> {
+    let media_props = session.TryGetMediaPropertiesAsync()?.get()?;
+    let thumbnail = match media_props.Thumbnail() {
+        Ok(thumbnail) => thumbnail,
+        // Players without a cover leave it null, which the bindings turn into an error with a success code
+        Err(err) if err.code().is_ok() => return Ok(None),
+        Err(err) => return Err(err),
+    };
+
+    let stream = thumbnail.OpenReadAsync()?.get()?;
+    let size = stream.Size()?;
+    if size == 0 || size > MAX_THUMBNAIL_SIZE {
+        return Ok(None);
+    }
+
+    let reader = DataReader::CreateDataReader(&stream)?;
+    let loaded = reader.LoadAsync(size as u32)?.get()?;
+    let mut data = vec![0; loaded as usize];
+    reader.ReadBytes(&mut data)?;
+
+    Ok(Some(Thumbnail {
+        content_type: stream.ContentType()?.to_string_lossy(),
+        data,
     }))
 }
 
+fn positive(value: i32) -> Option<u32> {
+    u32::try_from(value).ok().filter(|&value| value > 0)
+}
+
 // wrapper around i32 that verifies we got this number from windows and not just any i32.
 // probably unneeded but its still nice to have.
 struct RawStatusNumber(i32);
 struct RawMediaTypeNumber(i32);
+struct RawRepeatModeNumber(i32);
 
 impl From<RawStatusNumber> for MediaStatus {
     fn from(value: RawStatusNumber) -> Self {
@@ -101,6 +232,18 @@ impl From<RawMediaTypeNumber> for MediaType {
     }
 }
 
+impl From<RawRepeatModeNumber> for RepeatMode {
+    fn from(value: RawRepeatModeNumber) -> Self {
+        match value.0 {
+            0 => Self::None,
+            1 => Self::Track,
+            2 => Self::List,
+            // SAFETY: Using RawRepeatModeNumber we make sure that the only values we could get here are from the windows API itself
+            _ => unreachable!(),
+        }
+    }
+}
+
 fn get_raw_status_code(session: &GlobalSystemMediaTransportControlsSession) -> windows_result::Result<RawStatusNumber> {
     Ok(RawStatusNumber(session.GetPlaybackInfo()?.PlaybackStatus()?.0))
 }